@@ -0,0 +1,174 @@
+//! Awaiting user interrupts from async code.
+//!
+//! [`WAKE_HANDLER`] records each user interrupt in a pending bit of its vector
+//! and wakes the task awaiting [`UintrVector::next`]. It is installed like any
+//! other handler:
+//!
+//! ```rust,ignore
+//! use x86_uintr::{asynch::{UintrVector, WAKE_HANDLER}, handler::set_handler};
+//!
+//! set_handler(&WAKE_HANDLER);
+//! let mut vector = UintrVector::new(3);
+//! loop {
+//!     vector.next().await;
+//!     // handle the user interrupt
+//! }
+//! ```
+//!
+//! Wakers are woken from the handler, i.e., on the receiving thread with UIF
+//! cleared, so executors must not wait in their wakers for locks held by the
+//! receiving thread.
+
+use core::{
+    cell::UnsafeCell,
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+    task::{Context, Poll, Waker},
+};
+
+use crate::handler::{ContextHandler, NUM_VECTORS, UintrTrapframe};
+
+const WAITING: usize = 0;
+const REGISTERING: usize = 1;
+const WAKING: usize = 2;
+
+/// A waker slot that can be woken from a handler while it is being
+/// registered, without blocking.
+struct AtomicWaker {
+    state: AtomicUsize,
+    waker: UnsafeCell<Option<Waker>>,
+}
+
+// `waker` is only accessed by the holder of REGISTERING or WAKING.
+unsafe impl Sync for AtomicWaker {}
+
+impl AtomicWaker {
+    const fn new() -> Self {
+        Self {
+            state: AtomicUsize::new(WAITING),
+            waker: UnsafeCell::new(None),
+        }
+    }
+
+    fn register(&self, waker: &Waker) {
+        match self
+            .state
+            .compare_exchange(WAITING, REGISTERING, Ordering::Acquire, Ordering::Acquire)
+            .unwrap_or_else(|state| state)
+        {
+            WAITING => {
+                let previous = unsafe { (*self.waker.get()).replace(waker.clone()) };
+                if self
+                    .state
+                    .compare_exchange(REGISTERING, WAITING, Ordering::AcqRel, Ordering::Acquire)
+                    .is_err()
+                {
+                    // A wake-up arrived while registering and left the waker
+                    // to us.
+                    let waker = unsafe { (*self.waker.get()).take() };
+                    self.state.swap(WAITING, Ordering::AcqRel);
+                    if let Some(waker) = waker {
+                        waker.wake();
+                    }
+                }
+                drop(previous);
+            }
+            // A wake-up is in progress, which may miss `waker`.
+            WAKING => waker.wake_by_ref(),
+            // Registered concurrently by another task.
+            _ => {}
+        }
+    }
+
+    fn wake(&self) {
+        if self.state.fetch_or(WAKING, Ordering::AcqRel) == WAITING {
+            let waker = unsafe { (*self.waker.get()).take() };
+            self.state.fetch_and(!WAKING, Ordering::Release);
+            if let Some(waker) = waker {
+                waker.wake();
+            }
+        }
+    }
+}
+
+static PENDING: AtomicU64 = AtomicU64::new(0);
+static WAKERS: [AtomicWaker; NUM_VECTORS] = [const { AtomicWaker::new() }; NUM_VECTORS];
+
+/// Record a user interrupt with `vector` and wake the task awaiting it.
+///
+/// This is what [`WAKE_HANDLER`] does, for handlers that dispatch some
+/// vectors themselves.
+pub fn wake(vector: u8) {
+    let vector = vector as usize % NUM_VECTORS;
+    PENDING.fetch_or(1 << vector, Ordering::SeqCst);
+    WAKERS[vector].wake();
+}
+
+fn wake_handler(utf: &mut UintrTrapframe) {
+    wake(utf.info.uirr_vector as u8);
+}
+
+/// Handler that calls [`wake`] for the vector of each user interrupt, to be
+/// installed with [`set_handler`](crate::handler::set_handler) or
+/// [`register_vector_handler`](crate::handler::register_vector_handler).
+pub static WAKE_HANDLER: ContextHandler = ContextHandler::from_trapframe_fn(wake_handler);
+
+/// The user interrupts with one vector, as a stream of notifications.
+///
+/// User interrupts that arrive before the previous one has been awaited are
+/// coalesced, like in UIRR. Only one task should await a vector at a time.
+#[derive(Debug)]
+pub struct UintrVector {
+    vector: u8,
+}
+
+impl UintrVector {
+    /// # Panics
+    ///
+    /// Panics if `vector` is not below [`NUM_VECTORS`].
+    pub const fn new(vector: u8) -> Self {
+        assert!((vector as usize) < NUM_VECTORS);
+        Self { vector }
+    }
+
+    pub fn vector(&self) -> u8 {
+        self.vector
+    }
+
+    /// Consume a pending user interrupt, if any.
+    pub fn try_next(&self) -> bool {
+        let bit = 1 << self.vector;
+        PENDING.fetch_and(!bit, Ordering::SeqCst) & bit != 0
+    }
+
+    /// Wait for the next user interrupt.
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Next<'_> {
+        Next { vector: self }
+    }
+}
+
+/// Future returned by [`UintrVector::next`].
+#[derive(Debug)]
+#[must_use = "futures do nothing unless polled"]
+pub struct Next<'a> {
+    vector: &'a UintrVector,
+}
+
+impl Future for Next<'_> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if self.vector.try_next() {
+            return Poll::Ready(());
+        }
+        WAKERS[self.vector.vector as usize].register(cx.waker());
+        // Check again in case the user interrupt arrived before registering.
+        if self.vector.try_next() {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+}
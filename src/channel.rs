@@ -0,0 +1,473 @@
+//! Bounded lock-free channel whose senders notify the receiver with user
+//! interrupts.
+//!
+//! Like a UPID, the channel has an outstanding-notification flag (ON) and a
+//! suppress-notification flag (SN). A sender only notifies the receiver if
+//! neither is set, and sets ON when it does. Hence a receiver that is busy
+//! draining the channel is not interrupted for every message:
+//!
+//! ```rust,ignore
+//! let mut receiver = CHANNEL.receiver().unwrap();
+//! loop {
+//!     while let Some(message) = receiver.try_recv() {
+//!         handle(message);
+//!     }
+//!     // Clear ON and SN, and sleep unless messages arrived meanwhile.
+//!     if receiver.prepare_wait() {
+//!         wait_for_user_interrupt();
+//!     }
+//! }
+//! ```
+//!
+//! With the `async` feature, `Receiver::recv` awaits the notifications
+//! instead, as dispatched to `asynch::WAKE_HANDLER`.
+//!
+//! Notifications go through a [`Notifier`], which is [`UipiNotifier`] with
+//! user interrupts, or a closure for polling and testing without UINTR
+//! hardware.
+
+use core::{
+    cell::UnsafeCell,
+    fmt::{self, Debug, Formatter},
+    mem::MaybeUninit,
+    sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering, fence},
+};
+
+use crate::instructions::send_uipi;
+
+/// Suppress notification: the receiver is polling.
+const SN: u32 = 1 << 0;
+/// Outstanding notification: the receiver has been notified.
+const ON: u32 = 1 << 1;
+
+/// Wakes up the receiver of a channel.
+pub trait Notifier {
+    fn notify(&self);
+}
+
+impl<F: Fn()> Notifier for F {
+    fn notify(&self) {
+        self()
+    }
+}
+
+/// Notifies the receiver with SENDUIPI through a UITT entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UipiNotifier {
+    index: u64,
+}
+
+impl UipiNotifier {
+    /// # Safety
+    ///
+    /// `index` must satisfy the requirements of [`send_uipi`] whenever a
+    /// message is sent.
+    pub unsafe fn new(index: u64) -> Self {
+        Self { index }
+    }
+
+    pub fn index(&self) -> u64 {
+        self.index
+    }
+}
+
+impl Notifier for UipiNotifier {
+    fn notify(&self) {
+        unsafe { send_uipi(self.index) }
+    }
+}
+
+struct Slot<T> {
+    /// Position at which the slot can be written, or position + 1 once it
+    /// has been written and can be read.
+    seq: AtomicUsize,
+    value: UnsafeCell<MaybeUninit<T>>,
+}
+
+/// A multi-producer single-consumer queue of up to `N` messages, with the
+/// notification flags of its receiver.
+pub struct Channel<T, const N: usize> {
+    slots: [Slot<T>; N],
+    /// Next position to write.
+    tail: AtomicUsize,
+    /// Next position to read, only advanced by the receiver.
+    head: AtomicUsize,
+    control: AtomicU32,
+    receiver_taken: AtomicBool,
+}
+
+// Values are moved from senders to the receiver, which may run on other threads.
+unsafe impl<T: Send, const N: usize> Sync for Channel<T, N> {}
+unsafe impl<T: Send, const N: usize> Send for Channel<T, N> {}
+
+impl<T, const N: usize> Channel<T, N> {
+    /// # Panics
+    ///
+    /// Panics if `N` is 0.
+    pub const fn new() -> Self {
+        assert!(N > 0);
+        let mut slots = [const {
+            Slot {
+                seq: AtomicUsize::new(0),
+                value: UnsafeCell::new(MaybeUninit::uninit()),
+            }
+        }; N];
+        let mut i = 0;
+        while i < N {
+            slots[i].seq = AtomicUsize::new(i);
+            i += 1;
+        }
+        Self {
+            slots,
+            tail: AtomicUsize::new(0),
+            head: AtomicUsize::new(0),
+            control: AtomicU32::new(0),
+            receiver_taken: AtomicBool::new(false),
+        }
+    }
+
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+
+    /// A sending side notifying the receiver with `notifier`.
+    pub fn sender<Nt: Notifier>(&self, notifier: Nt) -> Sender<'_, T, N, Nt> {
+        Sender {
+            channel: self,
+            notifier,
+        }
+    }
+
+    /// The receiving side, or `None` if it has already been taken.
+    pub fn receiver(&self) -> Option<Receiver<'_, T, N>> {
+        (!self.receiver_taken.swap(true, Ordering::AcqRel)).then_some(Receiver { channel: self })
+    }
+
+    /// Whether the receiver has been notified and not yet called
+    /// [`Receiver::prepare_wait`].
+    pub fn is_notified(&self) -> bool {
+        self.control.load(Ordering::SeqCst) & ON != 0
+    }
+
+    fn push(&self, value: T) -> Result<(), T> {
+        let mut pos = self.tail.load(Ordering::Relaxed);
+        loop {
+            let slot = &self.slots[pos % N];
+            let seq = slot.seq.load(Ordering::Acquire);
+            if seq == pos {
+                match self.tail.compare_exchange_weak(
+                    pos,
+                    pos.wrapping_add(1),
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => {
+                        unsafe { (*slot.value.get()).write(value) };
+                        slot.seq.store(pos.wrapping_add(1), Ordering::Release);
+                        return Ok(());
+                    }
+                    Err(current) => pos = current,
+                }
+            } else if (seq.wrapping_sub(pos) as isize) < 0 {
+                // The slot still holds the message from N positions ago.
+                return Err(value);
+            } else {
+                pos = self.tail.load(Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Only called by the receiver.
+    fn pop(&self) -> Option<T> {
+        let pos = self.head.load(Ordering::Relaxed);
+        let slot = &self.slots[pos % N];
+        if slot.seq.load(Ordering::Acquire) != pos.wrapping_add(1) {
+            return None;
+        }
+        let value = unsafe { (*slot.value.get()).assume_init_read() };
+        slot.seq.store(pos.wrapping_add(N), Ordering::Release);
+        self.head.store(pos.wrapping_add(1), Ordering::Relaxed);
+        Some(value)
+    }
+
+    fn is_empty(&self) -> bool {
+        let pos = self.head.load(Ordering::Relaxed);
+        self.slots[pos % N].seq.load(Ordering::Acquire) != pos.wrapping_add(1)
+    }
+
+    /// Set ON unless SN or ON is set, and return whether the receiver has to
+    /// be notified.
+    fn post(&self) -> bool {
+        self.control
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |control| {
+                (control & (SN | ON) == 0).then_some(control | ON)
+            })
+            .is_ok()
+    }
+}
+
+impl<T, const N: usize> Drop for Channel<T, N> {
+    fn drop(&mut self) {
+        while self.pop().is_some() {}
+    }
+}
+
+impl<T, const N: usize> Debug for Channel<T, N> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let control = self.control.load(Ordering::SeqCst);
+        f.debug_struct("Channel")
+            .field("capacity", &N)
+            .field("head", &self.head.load(Ordering::Relaxed))
+            .field("tail", &self.tail.load(Ordering::Relaxed))
+            .field("SN", &(control & SN != 0))
+            .field("ON", &(control & ON != 0))
+            .finish()
+    }
+}
+
+/// Sending side of a [`Channel`].
+pub struct Sender<'a, T, const N: usize, Nt: Notifier> {
+    channel: &'a Channel<T, N>,
+    notifier: Nt,
+}
+
+impl<T, const N: usize, Nt: Notifier> Sender<'_, T, N, Nt> {
+    /// Enqueue `value`, and notify the receiver unless it is polling or has
+    /// already been notified. Returns `value` back if the channel is full.
+    pub fn send(&self, value: T) -> Result<(), T> {
+        self.channel.push(value)?;
+        // Order the enqueue before reading the flags, which the receiver
+        // clears before checking for messages.
+        fence(Ordering::SeqCst);
+        if self.channel.post() {
+            self.notifier.notify();
+        }
+        Ok(())
+    }
+
+    pub fn notifier(&self) -> &Nt {
+        &self.notifier
+    }
+}
+
+impl<T, const N: usize, Nt: Notifier + Clone> Clone for Sender<'_, T, N, Nt> {
+    fn clone(&self) -> Self {
+        Self {
+            channel: self.channel,
+            notifier: self.notifier.clone(),
+        }
+    }
+}
+
+impl<T, const N: usize, Nt: Notifier + Debug> Debug for Sender<'_, T, N, Nt> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("Sender")
+            .field("channel", self.channel)
+            .field("notifier", &self.notifier)
+            .finish()
+    }
+}
+
+/// Receiving side of a [`Channel`].
+pub struct Receiver<'a, T, const N: usize> {
+    channel: &'a Channel<T, N>,
+}
+
+impl<T, const N: usize> Receiver<'_, T, N> {
+    /// Dequeue the oldest message, if any.
+    pub fn try_recv(&mut self) -> Option<T> {
+        self.channel.pop()
+    }
+
+    /// Set SN, so that senders do not notify while the receiver is polling.
+    pub fn suppress(&mut self) {
+        self.channel.control.fetch_or(SN, Ordering::SeqCst);
+    }
+
+    /// Clear ON and SN, so that the next message notifies the receiver.
+    ///
+    /// Returns `false` if messages are already queued, in which case the
+    /// receiver must receive them instead of waiting for a notification.
+    pub fn prepare_wait(&mut self) -> bool {
+        self.channel.control.fetch_and(!(SN | ON), Ordering::SeqCst);
+        fence(Ordering::SeqCst);
+        self.channel.is_empty()
+    }
+
+    /// Receive a message, calling `wait` whenever the channel is empty.
+    ///
+    /// `wait` is called after [`Receiver::prepare_wait`] and must return once
+    /// a notification has arrived since then, e.g., by checking a flag set by
+    /// the user-interrupt handler. Spinning is a valid `wait`.
+    pub fn recv_with(&mut self, mut wait: impl FnMut()) -> T {
+        loop {
+            if let Some(value) = self.try_recv() {
+                return value;
+            }
+            if self.prepare_wait() {
+                wait();
+            }
+        }
+    }
+
+    /// Receive a message, awaiting `vector` whenever the channel is empty.
+    ///
+    /// The senders' notifier must send user interrupts with `vector`, which
+    /// are handled by [`WAKE_HANDLER`](crate::asynch::WAKE_HANDLER).
+    #[cfg(feature = "async")]
+    pub async fn recv(&mut self, vector: &mut crate::asynch::UintrVector) -> T {
+        loop {
+            if let Some(value) = self.try_recv() {
+                return value;
+            }
+            if self.prepare_wait() {
+                vector.next().await;
+            }
+        }
+    }
+}
+
+impl<T, const N: usize> Drop for Receiver<'_, T, N> {
+    fn drop(&mut self) {
+        self.channel.receiver_taken.store(false, Ordering::Release);
+    }
+}
+
+impl<T, const N: usize> Debug for Receiver<'_, T, N> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("Receiver")
+            .field("channel", self.channel)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::{sync::Arc, thread};
+
+    use super::*;
+
+    #[test]
+    fn wrap_around() {
+        let channel = Channel::<usize, 4>::new();
+        let sender = channel.sender(|| {});
+        let mut receiver = channel.receiver().unwrap();
+        for i in 0..100 {
+            sender.send(i).unwrap();
+            sender.send(i + 1000).unwrap();
+            assert_eq!(receiver.try_recv(), Some(i));
+            assert_eq!(receiver.try_recv(), Some(i + 1000));
+            assert_eq!(receiver.try_recv(), None);
+        }
+    }
+
+    #[test]
+    fn full() {
+        let channel = Channel::<usize, 4>::new();
+        let sender = channel.sender(|| {});
+        let mut receiver = channel.receiver().unwrap();
+        for i in 0..4 {
+            sender.send(i).unwrap();
+        }
+        assert_eq!(sender.send(4), Err(4));
+        assert_eq!(receiver.try_recv(), Some(0));
+        sender.send(4).unwrap();
+        assert_eq!(sender.send(5), Err(5));
+        for i in 1..5 {
+            assert_eq!(receiver.try_recv(), Some(i));
+        }
+        assert_eq!(receiver.try_recv(), None);
+    }
+
+    #[test]
+    fn notifications() {
+        let notified = AtomicUsize::new(0);
+        let channel = Channel::<u32, 8>::new();
+        let sender = channel.sender(|| {
+            notified.fetch_add(1, Ordering::SeqCst);
+        });
+        let mut receiver = channel.receiver().unwrap();
+
+        // Coalesced until the receiver prepares to wait again.
+        sender.send(1).unwrap();
+        sender.send(2).unwrap();
+        assert_eq!(notified.load(Ordering::SeqCst), 1);
+        assert!(channel.is_notified());
+        assert!(!receiver.prepare_wait());
+        assert!(!channel.is_notified());
+        assert_eq!(receiver.try_recv(), Some(1));
+        assert_eq!(receiver.try_recv(), Some(2));
+        assert!(receiver.prepare_wait());
+        sender.send(3).unwrap();
+        assert_eq!(notified.load(Ordering::SeqCst), 2);
+
+        // Suppressed while polling.
+        assert_eq!(receiver.try_recv(), Some(3));
+        assert!(receiver.prepare_wait());
+        receiver.suppress();
+        sender.send(4).unwrap();
+        assert_eq!(notified.load(Ordering::SeqCst), 2);
+        assert!(!channel.is_notified());
+        assert!(!receiver.prepare_wait());
+        assert_eq!(receiver.try_recv(), Some(4));
+    }
+
+    #[test]
+    fn single_receiver() {
+        let channel = Channel::<u32, 2>::new();
+        let receiver = channel.receiver().unwrap();
+        assert!(channel.receiver().is_none());
+        drop(receiver);
+        assert!(channel.receiver().is_some());
+    }
+
+    #[test]
+    fn drop_pending() {
+        let value = Arc::new(());
+        let channel = Channel::<Arc<()>, 4>::new();
+        channel.sender(|| {}).send(value.clone()).unwrap();
+        assert_eq!(Arc::strong_count(&value), 2);
+        drop(channel);
+        assert_eq!(Arc::strong_count(&value), 1);
+    }
+
+    #[test]
+    fn producers() {
+        const PRODUCERS: usize = 4;
+        const MESSAGES: usize = 10_000;
+        let channel = Channel::<(usize, usize), 16>::new();
+        let notified = AtomicBool::new(false);
+        let notifier = || notified.store(true, Ordering::SeqCst);
+        let mut receiver = channel.receiver().unwrap();
+        thread::scope(|scope| {
+            for producer in 0..PRODUCERS {
+                let sender = channel.sender(notifier);
+                scope.spawn(move || {
+                    for i in 0..MESSAGES {
+                        let mut message = (producer, i);
+                        while let Err(full) = sender.send(message) {
+                            message = full;
+                            thread::yield_now();
+                        }
+                    }
+                });
+            }
+
+            let mut next = [0; PRODUCERS];
+            for _ in 0..PRODUCERS * MESSAGES {
+                let (producer, i) = receiver.recv_with(|| {
+                    while !notified.swap(false, Ordering::SeqCst) {
+                        thread::yield_now();
+                    }
+                });
+                // Messages of each producer arrive in order.
+                assert_eq!(i, next[producer]);
+                next[producer] += 1;
+            }
+            assert_eq!(next, [MESSAGES; PRODUCERS]);
+        });
+        assert_eq!(receiver.try_recv(), None);
+    }
+}
@@ -0,0 +1,119 @@
+//! Per-CPU bring-up of user interrupts: CR4.UINTR and MSR initialization.
+
+use core::{arch::asm, marker::PhantomData};
+
+use crate::{
+    detect::has_uintr,
+    instructions::clui,
+    msr::{UintrMsr, X86_CR4_UINTR},
+};
+
+/// Reasons why user interrupts cannot be enabled on the current CPU.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EnableError {
+    /// CPUID.(EAX=07H,ECX=0):EDX\[5\] is clear.
+    Unsupported,
+    /// CR4.UINTR is already set, e.g., by another [`UintrEnabled`] guard.
+    AlreadyEnabled,
+}
+
+// `x86::controlregs::cr4()` truncates the bits unknown to its `Cr4` type,
+// which includes CR4.UINTR, so CR4 is accessed directly here.
+#[inline]
+unsafe fn read_cr4() -> u64 {
+    let value: u64;
+    unsafe { asm!("mov {}, cr4", out(reg) value, options(nomem, nostack, preserves_flags)) };
+    value
+}
+
+#[inline]
+unsafe fn write_cr4(value: u64) {
+    unsafe { asm!("mov cr4, {}", in(reg) value, options(nostack, preserves_flags)) };
+}
+
+/// Whether CR4.UINTR is set on the current CPU.
+///
+/// # Safety
+///
+/// Must be executed at CPL 0.
+#[inline]
+pub unsafe fn is_enabled() -> bool {
+    unsafe { read_cr4() & X86_CR4_UINTR as u64 != 0 }
+}
+
+/// Set CR4.UINTR on the current CPU, clear UIF and reset all UINTR MSRs to 0.
+///
+/// Fails with [`EnableError::AlreadyEnabled`] if CR4.UINTR is already set,
+/// without touching the UINTR state loaded on this CPU.
+///
+/// # Safety
+///
+/// Must be executed at CPL 0.
+pub unsafe fn enable() -> Result<(), EnableError> {
+    if !has_uintr() {
+        return Err(EnableError::Unsupported);
+    }
+    unsafe {
+        if is_enabled() {
+            return Err(EnableError::AlreadyEnabled);
+        }
+        write_cr4(read_cr4() | X86_CR4_UINTR as u64);
+        reset_msrs();
+    }
+    clui();
+    Ok(())
+}
+
+/// Reset all UINTR MSRs to 0 and clear CR4.UINTR on the current CPU.
+///
+/// Does nothing if CR4.UINTR is not set.
+///
+/// # Safety
+///
+/// Must be executed at CPL 0. Any UINTR state loaded on this CPU is discarded.
+pub unsafe fn disable() {
+    unsafe {
+        if !is_enabled() {
+            return;
+        }
+        reset_msrs();
+        write_cr4(read_cr4() & !(X86_CR4_UINTR as u64));
+    }
+}
+
+#[inline]
+unsafe fn reset_msrs() {
+    for msr in UintrMsr::ALL {
+        unsafe { msr.write(0) };
+    }
+}
+
+/// RAII guard keeping user interrupts enabled on the current CPU.
+///
+/// The guard disables user interrupts when dropped. Only one guard can exist
+/// per CPU, since creating it fails if user interrupts are already enabled.
+/// It is neither `Send` nor `Sync`, since CR4 is a per-CPU register.
+#[derive(Debug)]
+pub struct UintrEnabled {
+    _not_send: PhantomData<*const ()>,
+}
+
+impl UintrEnabled {
+    /// Enable user interrupts on the current CPU, see [`enable`].
+    ///
+    /// # Safety
+    ///
+    /// Must be executed at CPL 0, and the guard must be dropped on the same CPU.
+    pub unsafe fn new() -> Result<Self, EnableError> {
+        unsafe { enable()? };
+        Ok(Self {
+            _not_send: PhantomData,
+        })
+    }
+}
+
+impl Drop for UintrEnabled {
+    fn drop(&mut self) {
+        unsafe { disable() };
+    }
+}
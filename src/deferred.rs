@@ -0,0 +1,127 @@
+//! Deferred work of user-interrupt handlers.
+//!
+//! Handlers run with UIF cleared, so they should only [`defer`] the bulk of
+//! their work, which the interrupted thread runs after UIRET at a point of its
+//! choice, with [`run_pending`] or [`enable_uirqs`]:
+//!
+//! ```rust,ignore
+//! fn handler(tf: &mut UintrTrapframe) {
+//!     let _ = deferred::defer(process, tf.info.uirr_vector as usize);
+//! }
+//!
+//! loop {
+//!     do_work();
+//!     deferred::run_pending();
+//! }
+//! ```
+//!
+//! With the `per_thread` feature, every thread has its own queue, so tasks
+//! deferred by a handler run on the thread it interrupted. Otherwise, the
+//! queue is shared by all threads, and tasks run on whichever thread drains
+//! it.
+
+use crate::{
+    channel::Channel,
+    instructions::{self, UintrGuard},
+};
+
+/// Number of tasks that can be pending.
+pub const CAPACITY: usize = 64;
+
+/// A function with its argument.
+#[derive(Debug, Clone, Copy)]
+pub struct Task {
+    pub func: fn(usize),
+    pub arg: usize,
+}
+
+impl Task {
+    pub fn run(self) {
+        (self.func)(self.arg)
+    }
+}
+
+// With the `per_thread` feature, every thread has its own queue.
+#[cfg_attr(feature = "per_thread", thread_local)]
+static QUEUE: Channel<Task, CAPACITY> = Channel::new();
+
+/// Queue `func(arg)` to run at the next [`run_pending`].
+///
+/// This never blocks, so it can be called from handlers. With the
+/// `per_thread` feature, the task is queued for the calling thread. Returns
+/// the task back if [`CAPACITY`] tasks are already pending.
+pub fn defer(func: fn(usize), arg: usize) -> Result<(), Task> {
+    QUEUE.sender(|| {}).send(Task { func, arg })
+}
+
+/// Run the pending tasks, including those deferred meanwhile, and return how
+/// many ran.
+///
+/// Tasks run on the calling thread with the current UIF. Calls from a task
+/// return 0, and so do calls while another thread runs the shared queue,
+/// without the `per_thread` feature.
+pub fn run_pending() -> usize {
+    let Some(mut receiver) = QUEUE.receiver() else {
+        return 0;
+    };
+    let mut ran = 0;
+    while let Some(task) = receiver.try_recv() {
+        task.run();
+        ran += 1;
+    }
+    ran
+}
+
+/// Enable user interrupts, and run the tasks deferred while they were
+/// disabled.
+pub fn enable_uirqs() {
+    instructions::enable_uirqs();
+    run_pending();
+}
+
+/// Drop `guard`, and run the pending tasks if this enables user interrupts.
+pub fn release(guard: UintrGuard) {
+    let enabled = guard.was_enabled();
+    drop(guard);
+    if enabled {
+        run_pending();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    static SUM: AtomicUsize = AtomicUsize::new(0);
+
+    fn add(arg: usize) {
+        SUM.fetch_add(arg, Ordering::SeqCst);
+    }
+
+    fn nested(_: usize) {
+        assert_eq!(run_pending(), 0);
+    }
+
+    #[test]
+    fn run_deferred() {
+        defer(add, 1).unwrap();
+        defer(nested, 0).unwrap();
+        defer(add, 2).unwrap();
+        // Another thread has a queue of its own.
+        #[cfg(feature = "per_thread")]
+        assert_eq!(std::thread::spawn(run_pending).join().unwrap(), 0);
+        assert_eq!(run_pending(), 3);
+        assert_eq!(SUM.load(Ordering::SeqCst), 3);
+        assert_eq!(run_pending(), 0);
+
+        for _ in 0..CAPACITY {
+            defer(add, 0).unwrap();
+        }
+        assert!(defer(add, 0).is_err());
+        assert_eq!(run_pending(), CAPACITY);
+    }
+}
@@ -0,0 +1,75 @@
+//! CPUID-based detection of UINTR support.
+
+use x86::cpuid::native_cpuid::cpuid_count;
+
+/// XSAVE state component number of the user-interrupt state.
+pub const XFEATURE_UINTR: u32 = 14;
+/// Bit of the user-interrupt state component in IA32_XSS / XCR0-style masks.
+pub const XFEATURE_MASK_UINTR: u64 = 1 << XFEATURE_UINTR;
+
+/// Capability report of the current logical processor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct UintrCapabilities {
+    /// CPUID.(EAX=07H,ECX=0):EDX\[5\], the processor supports user interrupts.
+    pub uintr: bool,
+    /// CPUID.(EAX=0DH,ECX=1):EAX\[3\], the processor supports XSAVES/XRSTORS.
+    pub xsaves: bool,
+    /// CPUID.(EAX=0DH,ECX=1):ECX\[14\], state component 14 can be enabled in IA32_XSS.
+    pub xsaves_uintr: bool,
+    /// CPUID.(EAX=0DH,ECX=14):EAX, size in bytes of the user-interrupt state component.
+    pub xstate_size: u32,
+    /// CPUID.(EAX=0DH,ECX=14):EBX, offset of the component in the standard format.
+    /// This is always 0 for supervisor state components, which can only be saved
+    /// in the compacted format.
+    pub xstate_offset: u32,
+    /// CPUID.(EAX=0DH,ECX=14):ECX\[1\], the component is 64-byte aligned in the
+    /// compacted format.
+    pub xstate_aligned: bool,
+    /// CPUID.01H:ECX\[21\], the processor supports x2APIC mode.
+    pub x2apic: bool,
+    /// CPUID.(EAX=07H,ECX=0):ECX\[5\], the processor supports UMONITOR, UMWAIT
+    /// and TPAUSE.
+    pub waitpkg: bool,
+}
+
+impl UintrCapabilities {
+    /// Query CPUID on the current logical processor.
+    pub fn detect() -> Self {
+        let max_leaf = cpuid_count(0, 0).eax;
+        let x2apic = cpuid_count(1, 0).ecx & (1 << 21) != 0;
+        let features = (max_leaf >= 7).then(|| cpuid_count(7, 0));
+        let uintr = features.is_some_and(|features| features.edx & (1 << 5) != 0);
+        let waitpkg = features.is_some_and(|features| features.ecx & (1 << 5) != 0);
+
+        let mut caps = Self {
+            uintr,
+            x2apic,
+            waitpkg,
+            ..Self::default()
+        };
+        if max_leaf >= 0xd {
+            let xsave_ext = cpuid_count(0xd, 1);
+            caps.xsaves = xsave_ext.eax & (1 << 3) != 0;
+            caps.xsaves_uintr = xsave_ext.ecx & (1 << XFEATURE_UINTR) != 0;
+            if caps.xsaves_uintr {
+                let component = cpuid_count(0xd, XFEATURE_UINTR);
+                caps.xstate_size = component.eax;
+                caps.xstate_offset = component.ebx;
+                caps.xstate_aligned = component.ecx & (1 << 1) != 0;
+            }
+        }
+        caps
+    }
+
+    /// Whether the user-interrupt state can be saved and restored with
+    /// XSAVES/XRSTORS instead of individual MSR accesses.
+    pub fn supports_xsaves(&self) -> bool {
+        self.uintr && self.xsaves && self.xsaves_uintr
+    }
+}
+
+/// Whether the current logical processor supports user interrupts.
+#[inline]
+pub fn has_uintr() -> bool {
+    UintrCapabilities::detect().uintr
+}
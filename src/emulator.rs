@@ -0,0 +1,445 @@
+//! Software model of SENDUIPI, user-interrupt notification processing and
+//! user-interrupt delivery.
+//!
+//! The emulator operates on the crate's real data structures: each
+//! [`EmulatedCpu`] keeps its user-interrupt MSRs and UIF in a [`FakeMsr`],
+//! which is loaded and saved with [`UintrState::restore_all_with`] and
+//! [`UintrState::save_all_with`]. UITTADDR and UPIDADDR are dereferenced as
+//! pointers into the host process, so UITTs and UPIDs are ordinary
+//! [`UittEntry`] arrays and [`Upid`]s owned by the test.
+//!
+//! [`UittEntry`]: crate::uitte::UittEntry
+
+use std::vec::Vec;
+
+use crate::{
+    msr::{
+        FakeMsr, Misc, MiscLocal, MsrAccess, PostDesc, PostDescLocal, StackAdjust,
+        StackAdjustLocal, UintrMsr,
+    },
+    state::UintrState,
+    uitt::UittFault,
+    upid::{ApicId, AtomicUpid, Upid},
+};
+
+const RFLAGS_TF: u64 = 1 << 8;
+const RFLAGS_RF: u64 = 1 << 16;
+
+/// Conditions under which SENDUIPI raises #GP.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SendFault {
+    /// The UITT or the selected entry cannot be used.
+    Uitt(UittFault),
+    /// The UPID sets reserved bits in its notification control word.
+    ReservedUpidBits,
+}
+
+impl From<UittFault> for SendFault {
+    fn from(fault: UittFault) -> Self {
+        Self::Uitt(fault)
+    }
+}
+
+/// Stack frame pushed by user-interrupt delivery, lowest address first.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct DeliveryFrame {
+    /// Address of the frame, i.e., RSP when the handler is entered.
+    pub frame_addr: u64,
+    /// UIRRV, the vector being delivered.
+    pub uirr_vector: u64,
+    /// RIP of the interrupted code.
+    pub rip: u64,
+    /// RFLAGS of the interrupted code.
+    pub rflags: u64,
+    /// RSP of the interrupted code.
+    pub rsp: u64,
+}
+
+/// A logical processor with user interrupts enabled in CR4.
+#[derive(Debug)]
+pub struct EmulatedCpu {
+    /// APIC ID of this processor.
+    pub apic_id: ApicId,
+    /// User-interrupt MSRs and UIF.
+    pub msr: FakeMsr,
+    /// Whether the processor executes at CPL 3.
+    pub user_mode: bool,
+    /// Instruction pointer.
+    pub rip: u64,
+    /// Stack pointer.
+    pub rsp: u64,
+    /// Flags register.
+    pub rflags: u64,
+    /// Ordinary interrupts that were not treated as user-interrupt
+    /// notifications, in arrival order. These are left to the kernel.
+    pub ordinary_interrupts: Vec<u8>,
+    frames: Vec<DeliveryFrame>,
+}
+
+impl EmulatedCpu {
+    /// A processor in user mode with all user-interrupt MSRs and UIF cleared.
+    pub fn new(apic_id: ApicId) -> Self {
+        Self {
+            apic_id,
+            msr: FakeMsr::new(),
+            user_mode: true,
+            rip: 0,
+            rsp: 0,
+            rflags: 0,
+            ordinary_interrupts: Vec::new(),
+            frames: Vec::new(),
+        }
+    }
+
+    /// UIRR of this processor.
+    pub fn uirr(&self) -> u64 {
+        self.msr.read(UintrMsr::IA32_UINTR_RR)
+    }
+
+    /// Stack frames of deliveries not yet returned from with UIRET,
+    /// innermost last.
+    pub fn frames(&self) -> &[DeliveryFrame] {
+        &self.frames
+    }
+
+    /// Whether a user interrupt would be delivered before the next instruction.
+    pub fn delivery_pending(&self) -> bool {
+        self.user_mode && self.msr.uif() && self.uirr() != 0
+    }
+
+    /// Deliver the highest-priority pending user interrupt, if any.
+    ///
+    /// UIRRV is the most significant bit set in UIRR. RSP is adjusted according
+    /// to UISTACKADJUST and aligned to 16 bytes, the frame is pushed, UIF,
+    /// RFLAGS.TF and RFLAGS.RF are cleared, and RIP is loaded from UIHANDLER.
+    pub fn deliver(&mut self) -> Option<DeliveryFrame> {
+        if !self.delivery_pending() {
+            return None;
+        }
+        let uirr = self.uirr();
+        let uirrv = 63 - uirr.leading_zeros() as u64;
+        unsafe {
+            self.msr
+                .write(UintrMsr::IA32_UINTR_RR, uirr & !(1 << uirrv))
+        };
+
+        let stack_adjust = StackAdjustLocal::new(self.msr.read(UintrMsr::IA32_UINTR_STACKADJUST));
+        let mut rsp = if stack_adjust.matches_all(StackAdjust::MODE::Load) {
+            stack_adjust.get()
+        } else {
+            self.rsp.wrapping_sub(stack_adjust.get())
+        };
+        rsp &= !0xf;
+        rsp = rsp.wrapping_sub(32);
+
+        let frame = DeliveryFrame {
+            frame_addr: rsp,
+            uirr_vector: uirrv,
+            rip: self.rip,
+            rflags: self.rflags,
+            rsp: self.rsp,
+        };
+        self.frames.push(frame);
+        self.msr.set_uif(false);
+        self.rflags &= !(RFLAGS_TF | RFLAGS_RF);
+        self.rsp = rsp;
+        self.rip = self.msr.read(UintrMsr::IA32_UINTR_HANDLER);
+        Some(frame)
+    }
+
+    /// Execute UIRET from the innermost delivery frame: skip UIRRV, restore
+    /// RIP, RFLAGS and RSP, and set UIF.
+    ///
+    /// Returns the frame that was popped, or `None` if no delivery is active.
+    pub fn uiret(&mut self) -> Option<DeliveryFrame> {
+        let frame = self.frames.pop()?;
+        self.rip = frame.rip;
+        self.rflags = frame.rflags;
+        self.rsp = frame.rsp;
+        self.msr.set_uif(true);
+        Some(frame)
+    }
+
+    /// Process an ordinary interrupt with `vector` arriving at this processor.
+    ///
+    /// If the processor is in user mode and `vector` is UINV, user-interrupt
+    /// notification processing clears UPID.ON and moves PIR into UIRR.
+    /// Otherwise the interrupt is recorded in `ordinary_interrupts`.
+    ///
+    /// # Safety
+    ///
+    /// If `vector` is UINV, UPIDADDR must point to a valid [`Upid`].
+    pub unsafe fn interrupt(&mut self, vector: u8) {
+        let misc = MiscLocal::new(self.msr.read(UintrMsr::IA32_UINTR_MISC));
+        if !self.user_mode || misc.read(Misc::UINV) != vector as u64 {
+            self.ordinary_interrupts.push(vector);
+            return;
+        }
+        let post_desc = PostDescLocal::new(self.msr.read(UintrMsr::IA32_UINTR_PD));
+        let upid_addr = post_desc.get() & PostDesc::UPIDADDR::SET.mask();
+        let upid = unsafe { AtomicUpid::from_ptr(upid_addr as *mut Upid) };
+        upid.test_and_clear_outstanding();
+        let pir = upid.fetch_and_clear_pir();
+        let uirr = self.uirr() | pir;
+        unsafe { self.msr.write(UintrMsr::IA32_UINTR_RR, uirr) };
+    }
+}
+
+/// A set of logical processors connected by an interrupt bus.
+#[derive(Debug, Default)]
+pub struct Emulator {
+    cpus: Vec<EmulatedCpu>,
+}
+
+impl Emulator {
+    /// An emulator without processors.
+    pub fn new() -> Self {
+        Self { cpus: Vec::new() }
+    }
+
+    /// Add a processor with the given APIC ID and return its index.
+    pub fn add_cpu(&mut self, apic_id: ApicId) -> usize {
+        self.cpus.push(EmulatedCpu::new(apic_id));
+        self.cpus.len() - 1
+    }
+
+    /// The processor at `index`.
+    pub fn cpu(&self, index: usize) -> &EmulatedCpu {
+        &self.cpus[index]
+    }
+
+    /// The processor at `index`.
+    pub fn cpu_mut(&mut self, index: usize) -> &mut EmulatedCpu {
+        &mut self.cpus[index]
+    }
+
+    /// Execute SENDUIPI with `uitt_index` on processor `cpu`.
+    ///
+    /// The user interrupt is posted in the UPID referenced by the UITT entry.
+    /// If the UPID has neither SN nor ON set, ON is set and an ordinary
+    /// interrupt with the UPID's notification vector is sent to the processor
+    /// whose APIC ID matches the UPID's destination. Notifications to unknown
+    /// destinations are dropped.
+    ///
+    /// # Safety
+    ///
+    /// UITTADDR of `cpu` must point to UITTSZ+1 valid UITT entries, and the
+    /// UPIDADDR of the selected entry must point to a valid [`Upid`]. The
+    /// UPIDADDR of the target processor must be valid as well.
+    pub unsafe fn senduipi(&mut self, cpu: usize, uitt_index: u64) -> Result<(), SendFault> {
+        let mut sender = UintrState::default();
+        sender.save_sender_with(&self.cpus[cpu].msr);
+        let entry = unsafe { sender.uitt() }.get(uitt_index)?;
+        let upid_addr = entry.upid_addr();
+
+        let upid = unsafe { AtomicUpid::from_ptr(upid_addr as *mut Upid) };
+        if upid.load().validate_reserved().is_err() {
+            return Err(SendFault::ReservedUpidBits);
+        }
+        upid.post_and_notify(entry.uintr_vector() as u8, |notification| {
+            if let Some(target) = self
+                .cpus
+                .iter_mut()
+                .find(|c| c.apic_id.to_destination() == notification.destination)
+            {
+                unsafe { target.interrupt(notification.vector) };
+            }
+        });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tock_registers::LocalRegisterCopy;
+
+    use super::*;
+    use crate::{
+        msr::StackAdjustMode,
+        uitte::UittEntry,
+        upid::{NotificationControl, NotificationControlLocal},
+    };
+
+    const UINV: u8 = 0xec;
+    const HANDLER: u64 = 0x40_1000;
+    const RECEIVER: ApicId = ApicId::XApic(1);
+
+    /// CPU 0 sends through `uitt`, CPU 1 receives with `upid`.
+    fn setup(
+        uitt: &[UittEntry],
+        upid: &AtomicUpid,
+        stack_adjust: u64,
+        stack_mode: StackAdjustMode,
+    ) -> Emulator {
+        let mut emulator = Emulator::new();
+        let sender = emulator.add_cpu(ApicId::XApic(0));
+        let receiver = emulator.add_cpu(RECEIVER);
+
+        let mut state = UintrState::default();
+        state.set_sender(uitt.as_ptr() as u64, uitt.len() as u64 - 1, true);
+        state.restore_all_with(&mut emulator.cpu_mut(sender).msr);
+
+        let mut state = UintrState::default();
+        state.set_receiver(
+            HANDLER,
+            stack_adjust,
+            stack_mode,
+            UINV as u64,
+            true,
+            upid.as_ptr() as u64,
+        );
+        state.restore_all_with(&mut emulator.cpu_mut(receiver).msr);
+        let cpu = emulator.cpu_mut(receiver);
+        cpu.rip = 0x1234;
+        cpu.rsp = 0x7fff_0000_1238;
+        cpu.rflags = 0x202 | RFLAGS_TF | RFLAGS_RF;
+        emulator
+    }
+
+    #[test]
+    fn send_deliver_uiret() {
+        let upid = AtomicUpid::new(false, false, UINV, RECEIVER);
+        let uitt = [UittEntry::new(5, upid.as_ptr() as u64)];
+        let mut emulator = setup(&uitt, &upid, 128, StackAdjustMode::Subtract);
+
+        unsafe { emulator.senduipi(0, 0) }.unwrap();
+        // Notification processing moved PIR to UIRR and cleared ON.
+        let upid_now = upid.load();
+        assert_eq!(upid_now.posted_uirq.get(), 0);
+        assert!(!upid_now.control.is_set(NotificationControl::OUTSTANDING));
+        let cpu = emulator.cpu_mut(1);
+        assert_eq!(cpu.uirr(), 1 << 5);
+        assert!(cpu.ordinary_interrupts.is_empty());
+
+        let frame = cpu.deliver().unwrap();
+        assert_eq!(
+            frame,
+            DeliveryFrame {
+                frame_addr: ((0x7fff_0000_1238 - 128) & !0xf) - 32,
+                uirr_vector: 5,
+                rip: 0x1234,
+                rflags: 0x202 | RFLAGS_TF | RFLAGS_RF,
+                rsp: 0x7fff_0000_1238,
+            }
+        );
+        assert_eq!(cpu.uirr(), 0);
+        assert!(!cpu.msr.uif());
+        assert_eq!(cpu.rip, HANDLER);
+        assert_eq!(cpu.rsp, frame.frame_addr);
+        assert_eq!(cpu.rflags, 0x202);
+        assert_eq!(cpu.frames(), &[frame]);
+        assert_eq!(cpu.deliver(), None);
+
+        assert_eq!(cpu.uiret(), Some(frame));
+        assert!(cpu.msr.uif());
+        assert_eq!(
+            (cpu.rip, cpu.rsp, cpu.rflags),
+            (0x1234, 0x7fff_0000_1238, 0x202 | RFLAGS_TF | RFLAGS_RF)
+        );
+        assert!(cpu.frames().is_empty());
+        assert_eq!(cpu.uiret(), None);
+    }
+
+    #[test]
+    fn deliver_highest_vector_onto_loaded_stack() {
+        let upid = AtomicUpid::new(false, false, UINV, RECEIVER);
+        let uitt = [
+            UittEntry::new(3, upid.as_ptr() as u64),
+            UittEntry::new(40, upid.as_ptr() as u64),
+        ];
+        let mut emulator = setup(&uitt, &upid, 0x7fff_8000_0008, StackAdjustMode::Load);
+
+        // UIF is clear, so both user interrupts stay pending.
+        emulator.cpu_mut(1).msr.set_uif(false);
+        unsafe { emulator.senduipi(0, 0) }.unwrap();
+        unsafe { emulator.senduipi(0, 1) }.unwrap();
+        let cpu = emulator.cpu_mut(1);
+        assert_eq!(cpu.uirr(), 1 << 3 | 1 << 40);
+        assert_eq!(cpu.deliver(), None);
+
+        cpu.msr.set_uif(true);
+        let frame = cpu.deliver().unwrap();
+        assert_eq!(frame.uirr_vector, 40);
+        assert_eq!(frame.frame_addr, 0x7fff_8000_0000 - 32);
+        assert_eq!(cpu.uirr(), 1 << 3);
+        cpu.uiret();
+        assert_eq!(cpu.deliver().unwrap().uirr_vector, 3);
+    }
+
+    #[test]
+    fn suppressed_and_outstanding_notifications() {
+        let upid = AtomicUpid::new(false, true, UINV, RECEIVER);
+        let uitt = [UittEntry::new(7, upid.as_ptr() as u64)];
+        let mut emulator = setup(&uitt, &upid, 0, StackAdjustMode::Subtract);
+
+        // SN set: the user interrupt is only posted.
+        unsafe { emulator.senduipi(0, 0) }.unwrap();
+        assert_eq!(upid.load().posted_uirq.get(), 1 << 7);
+        assert_eq!(emulator.cpu(1).uirr(), 0);
+
+        // ON set: no further notification either.
+        upid.clear_suppressed();
+        upid.test_and_set_outstanding();
+        unsafe { emulator.senduipi(0, 0) }.unwrap();
+        assert_eq!(emulator.cpu(1).uirr(), 0);
+
+        upid.test_and_clear_outstanding();
+        unsafe { emulator.senduipi(0, 0) }.unwrap();
+        assert_eq!(emulator.cpu(1).uirr(), 1 << 7);
+    }
+
+    #[test]
+    fn ordinary_interrupts() {
+        let upid = AtomicUpid::new(false, false, UINV, RECEIVER);
+        let uitt = [UittEntry::new(7, upid.as_ptr() as u64)];
+        let mut emulator = setup(&uitt, &upid, 0, StackAdjustMode::Subtract);
+        let cpu = emulator.cpu_mut(1);
+
+        unsafe { cpu.interrupt(0x20) };
+        cpu.user_mode = false;
+        unsafe { cpu.interrupt(UINV) };
+        assert_eq!(cpu.ordinary_interrupts, [0x20, UINV]);
+        assert_eq!(cpu.uirr(), 0);
+    }
+
+    #[test]
+    fn illegal_notification_vector() {
+        let upid = AtomicUpid::new(false, false, 3, RECEIVER);
+        assert!(upid.load().validate().is_err());
+        let uitt = [UittEntry::new(7, upid.as_ptr() as u64)];
+        let mut emulator = setup(&uitt, &upid, 0, StackAdjustMode::Subtract);
+
+        // SENDUIPI accepts the UPID, and the notification is not UINV.
+        unsafe { emulator.senduipi(0, 0) }.unwrap();
+        assert_eq!(emulator.cpu(1).ordinary_interrupts, [3]);
+        assert_eq!(emulator.cpu(1).uirr(), 0);
+    }
+
+    #[test]
+    fn send_faults() {
+        let upid = AtomicUpid::new(false, false, UINV, RECEIVER);
+        let mut uitt = [UittEntry::new(7, upid.as_ptr() as u64)];
+        uitt[0].set_valid(false);
+        let mut emulator = setup(&uitt, &upid, 0, StackAdjustMode::Subtract);
+        assert_eq!(
+            unsafe { emulator.senduipi(0, 0) },
+            Err(SendFault::Uitt(UittFault::InvalidEntry))
+        );
+        assert_eq!(
+            unsafe { emulator.senduipi(0, 1) },
+            Err(SendFault::Uitt(UittFault::IndexOutOfRange))
+        );
+
+        let reserved = AtomicUpid::from_upid(Upid {
+            control: NotificationControlLocal::new(upid.control().get() | 1 << 2),
+            posted_uirq: LocalRegisterCopy::new(0),
+        });
+        let uitt = [UittEntry::new(7, reserved.as_ptr() as u64)];
+        let mut emulator = setup(&uitt, &upid, 0, StackAdjustMode::Subtract);
+        assert_eq!(
+            unsafe { emulator.senduipi(0, 0) },
+            Err(SendFault::ReservedUpidBits)
+        );
+        assert_eq!(reserved.load().posted_uirq.get(), 0);
+    }
+}
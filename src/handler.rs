@@ -1,6 +1,6 @@
 use atomic::Atomic;
 use bytemuck::NoUninit;
-use core::sync::atomic::Ordering;
+use core::sync::atomic::{AtomicPtr, Ordering};
 
 #[repr(C)]
 #[derive(Debug, Default, Clone, Copy)]
@@ -44,77 +44,224 @@ pub struct UintrTrapframe {
     pub info: UintrInfo,
 }
 
+macro_rules! push_trapframe {
+    () => {
+        "
+        // fill trapframe
+        push   rax
+        push   r15
+        push   r14
+        push   r13
+        push   r12
+        push   rbp
+        push   rbx
+        push   r11
+        push   r10
+        push   r9
+        push   r8
+        push   rcx
+        push   rdx
+        push   rsi
+        push   rdi
+        "
+    };
+}
+
+macro_rules! pop_trapframe {
+    () => {
+        "
+        // restore trap frame
+        pop   rdi;
+        pop   rsi;
+        pop   rdx;
+        pop   rcx;
+        pop   r8;
+        pop   r9;
+        pop   r10;
+        pop   r11
+        pop   rbx;
+        pop   rbp;
+        pop   r12;
+        pop   r13;
+        pop   r14;
+        pop   r15;
+        pop   rax;
+
+        // skip UIRRV
+        add   rsp, 8
+
+        uiret
+        nop
+        "
+    };
+}
+
 /// # SAFETY
 ///
 /// This function is the entry point of UINTR handler, and should not be called
 /// by any user code. Its address can be filled into the IA32_UINTR_HANDLER MSR.
+#[cfg(not(feature = "xsave_full"))]
 #[naked]
 #[allow(dead_code)]
 pub unsafe extern "C" fn uintr_handler_asm_entry() {
     unsafe {
         core::arch::naked_asm!(
+            push_trapframe!(),
             "
-            // fill trapframe
-            push   rax
-            push   r15
-            push   r14
-            push   r13
-            push   r12
-            push   rbp
-            push   rbx
-            push   r11
-            push   r10
-            push   r9
-            push   r8
-            push   rcx
-            push   rdx
-            push   rsi
-            push   rdi
-
             // set first argument to beginning of trapframe
             mov    rdi, rsp
 
+            // delivery aligns RSP to 16 bytes before pushing 4 qwords, so
+            // the 15 pushes above misalign it; realign it for the call
+            mov    rbp, rsp
+            and    rsp, -16
             call     uintr_handler_rust_entry
+            mov    rsp, rbp
+            ",
+            pop_trapframe!(),
+        );
+    }
+}
 
-            // restore trap frame
-            pop   rdi;
-            pop   rsi;
-            pop   rdx;
-            pop   rcx;
-            pop   r8;
-            pop   r9;
-            pop   r10;
-            pop   r11
-            pop   rbx;
-            pop   rbp;
-            pop   r12;
-            pop   r13;
-            pop   r14;
-            pop   r15;
-            pop   rax;
-
-            // skip UIRRV
-            add   rsp, 8
-
-            uiret
-            nop
+/// # SAFETY
+///
+/// This function is the entry point of UINTR handler, and should not be called
+/// by any user code. Its address can be filled into the IA32_UINTR_HANDLER MSR.
+#[cfg(feature = "xsave_full")]
+#[naked]
+#[allow(dead_code)]
+pub unsafe extern "C" fn uintr_handler_asm_entry() {
+    // XSAVEOPT is not used: its modified optimization would skip components
+    // that were restored from the same stack address by an earlier delivery,
+    // although the memory has been reused by the interrupted code since.
+    unsafe {
+        core::arch::naked_asm!(
+            push_trapframe!(),
             "
+            // reserve the XSAVE area below the trapframe
+            mov    rbp, rsp
+            mov    r12, qword ptr [rip + {mask}]
+            mov    r13, qword ptr [rip + {size}]
+            sub    rsp, r13
+            and    rsp, -64
+
+            // XRSTOR faults unless the header is zeroed
+            xor    eax, eax
+            mov    qword ptr [rsp + 512], rax
+            mov    qword ptr [rsp + 520], rax
+            mov    qword ptr [rsp + 528], rax
+            mov    qword ptr [rsp + 536], rax
+            mov    qword ptr [rsp + 544], rax
+            mov    qword ptr [rsp + 552], rax
+            mov    qword ptr [rsp + 560], rax
+            mov    qword ptr [rsp + 568], rax
+
+            mov    rax, r12
+            mov    rdx, r12
+            shr    rdx, 32
+            xsave64 [rsp]
+
+            mov    rdi, rbp
+            mov    rsi, rsp
+            mov    rdx, r13
+            call     uintr_handler_rust_entry
+
+            // restore the components saved above, even if the mask has
+            // been changed in the meantime
+            mov    rax, r12
+            mov    rdx, r12
+            shr    rdx, 32
+            xrstor64 [rsp]
+            mov    rsp, rbp
+            ",
+            pop_trapframe!(),
+            mask = sym xstate::XSAVE_MASK,
+            size = sym xstate::XSAVE_SIZE,
         );
     }
 }
 
+/// Declare the handler types and their adapters for the extra arguments
+/// passed to handlers besides the trapframe.
+macro_rules! handler_types {
+    ($($arg:ident: $ty:ty),*) => {
+        pub type HandlerType = fn(&mut UintrTrapframe $(, &mut $ty)*);
+
+        /// Handler receiving a context of type `T`.
+        pub type ContextFn<T> = fn(&T, &mut UintrTrapframe $(, &mut $ty)*);
+
+        /// Handler receiving an untyped context pointer.
+        pub type RawContextFn = fn(*mut (), &mut UintrTrapframe $(, &mut $ty)*);
+
+        const NOOP_HANDLER: UintrHandler = UintrHandler(|_ $(, _: &mut $ty)*| {});
+
+        static HANDLER: Atomic<UintrHandler> = atomic::Atomic::new(NOOP_HANDLER);
+
+        fn call_plain(slot: &Atomic<UintrHandler>, utf: &mut UintrTrapframe $(, $arg: &mut $ty)*) {
+            slot.load(Ordering::SeqCst).0(utf $(, $arg)*)
+        }
+
+        #[cfg(feature = "per_thread")]
+        fn call_thread_fn(_: *mut (), utf: &mut UintrTrapframe $(, $arg: &mut $ty)*) {
+            THREAD_FN.load(Ordering::SeqCst).0(utf $(, $arg)*)
+        }
+
+        fn call_trapframe_fn(f: *mut (), utf: &mut UintrTrapframe $(, _: &mut $ty)*) {
+            // `f` was converted from a `TrapframeFn` by `from_trapframe_fn`.
+            let f = unsafe { core::mem::transmute::<*mut (), TrapframeFn>(f) };
+            f(utf)
+        }
+
+        fn call_closure<F: Fn(&mut UintrTrapframe $(, &mut $ty)*)>(
+            f: &F,
+            utf: &mut UintrTrapframe
+            $(, $arg: &mut $ty)*
+        ) {
+            f(utf $(, $arg)*)
+        }
+
+        impl ContextHandler {
+            /// A handler that calls the closure `f`.
+            pub const fn from_fn<F: Fn(&mut UintrTrapframe $(, &mut $ty)*) + Sync>(
+                f: &'static F,
+            ) -> Self {
+                Self::with_context(call_closure::<F>, f)
+            }
+
+            /// A handler that calls `f` with the trapframe only, regardless of
+            /// the extended state passed to handlers.
+            pub const fn from_trapframe_fn(f: TrapframeFn) -> Self {
+                unsafe { Self::from_raw(call_trapframe_fn, f as *mut ()) }
+            }
+
+            #[inline]
+            fn call(&self, utf: &mut UintrTrapframe $(, $arg: &mut $ty)*) {
+                (self.func)(self.ctx, utf $(, $arg)*)
+            }
+        }
+    };
+}
+
+mod altstack;
+pub(crate) use altstack::debug_assert_off_alt_stack;
+pub use altstack::{AltStack, alt_stack, on_alt_stack, set_alt_stack};
+
 cfg_if::cfg_if! {
-    if #[cfg(feature = "fp_simd")] {
+    if #[cfg(feature = "xsave_full")] {
+        mod xstate;
+        pub use xstate::{XSaveArea, XSaveMaskError, set_xsave_mask, xsave_mask};
+        handler_types!(xsave: XSaveArea);
+    } else if #[cfg(feature = "fp_simd")] {
         pub use xsave::XSaveLegacy;
-        pub type HandlerType = fn(&mut UintrTrapframe, &mut XSaveLegacy);
-        static HANDLER: Atomic<UintrHandler> = atomic::Atomic::new(UintrHandler(|_, _| {}));
+        handler_types!(fxstate: XSaveLegacy);
     } else {
-        pub type HandlerType = fn(&mut UintrTrapframe);
-
-        static HANDLER: Atomic<UintrHandler> = atomic::Atomic::new(UintrHandler(|_| {}));
+        handler_types!();
     }
 }
 
+/// Handler that only needs the trapframe, see [`ContextHandler::from_trapframe_fn`].
+pub type TrapframeFn = fn(&mut UintrTrapframe);
+
 /// Wrapping around the handler function pointer so that we can impl NoUninit trait for it
 #[derive(Clone, Copy)]
 #[repr(transparent)]
@@ -123,27 +270,382 @@ pub struct UintrHandler(pub HandlerType);
 // Potential UB? https://github.com/Amanieu/atomic-rs/issues/35
 unsafe impl NoUninit for UintrHandler {}
 
+/// A handler bound to a context, such as the runtime object of a library.
+pub struct ContextHandler {
+    func: RawContextFn,
+    ctx: *mut (),
+}
+
+// `ctx` is either shared as `&T` with `T: Sync`, or promised to be usable
+// from any thread by the caller of `from_raw`.
+unsafe impl Sync for ContextHandler {}
+unsafe impl Send for ContextHandler {}
+
+impl ContextHandler {
+    /// A handler that calls `func` with `ctx`.
+    pub const fn with_context<T: Sync>(func: ContextFn<T>, ctx: &'static T) -> Self {
+        Self {
+            // `&T` and `*mut ()` are ABI-compatible, so calling `func` through
+            // `RawContextFn` with a pointer to `T` is sound.
+            func: unsafe { core::mem::transmute::<ContextFn<T>, RawContextFn>(func) },
+            ctx: ctx as *const T as *mut (),
+        }
+    }
+
+    /// A handler that calls `func` with the untyped pointer `ctx`.
+    ///
+    /// # Safety
+    ///
+    /// `func` must be sound to call with `ctx` from the handler of any thread
+    /// for as long as the handler is installed.
+    pub const unsafe fn from_raw(func: RawContextFn, ctx: *mut ()) -> Self {
+        Self { func, ctx }
+    }
+
+    pub fn context(&self) -> *mut () {
+        self.ctx
+    }
+}
+
+/// A handler that can be installed for a vector or as the default handler.
+#[derive(Clone, Copy)]
+pub enum Handler {
+    Fn(UintrHandler),
+    Context(&'static ContextHandler),
+}
+
+impl From<UintrHandler> for Handler {
+    fn from(handler: UintrHandler) -> Self {
+        Self::Fn(handler)
+    }
+}
+
+impl From<&'static ContextHandler> for Handler {
+    fn from(handler: &'static ContextHandler) -> Self {
+        Self::Context(handler)
+    }
+}
+
+/// Number of user-interrupt vectors.
+pub const NUM_VECTORS: usize = 64;
+
+// Every installed handler is represented by a `ContextHandler`. Function
+// handlers are stored in `VECTOR_FNS` and called through the adapters in
+// `VECTOR_FN_ADAPTERS`, or through `DEFAULT_FN_ADAPTER` for `HANDLER`.
+static VECTOR_FNS: [Atomic<UintrHandler>; NUM_VECTORS] =
+    [const { Atomic::new(NOOP_HANDLER) }; NUM_VECTORS];
+
+static VECTOR_FN_ADAPTERS: [ContextHandler; NUM_VECTORS] = {
+    let mut adapters = [const { ContextHandler::with_context(call_plain, &HANDLER) }; NUM_VECTORS];
+    let mut vector = 0;
+    while vector < NUM_VECTORS {
+        adapters[vector] = ContextHandler::with_context(call_plain, &VECTOR_FNS[vector]);
+        vector += 1;
+    }
+    adapters
+};
+
+static DEFAULT_FN_ADAPTER: ContextHandler = ContextHandler::with_context(call_plain, &HANDLER);
+
+static DEFAULT_HANDLER: AtomicPtr<ContextHandler> =
+    AtomicPtr::new(&DEFAULT_FN_ADAPTER as *const _ as *mut _);
+
+static VECTOR_HANDLERS: [AtomicPtr<ContextHandler>; NUM_VECTORS] =
+    [const { AtomicPtr::new(core::ptr::null_mut()) }; NUM_VECTORS];
+
+// The receiver state is per-thread, so each thread may install a handler of
+// its own. Function handlers of a thread are stored in `THREAD_FN` and called
+// through `THREAD_FN_ADAPTER`, which reads the slot of the current thread.
+#[cfg(feature = "per_thread")]
+#[thread_local]
+static THREAD_FN: Atomic<UintrHandler> = Atomic::new(NOOP_HANDLER);
+
+#[cfg(feature = "per_thread")]
+static THREAD_FN_ADAPTER: ContextHandler =
+    unsafe { ContextHandler::from_raw(call_thread_fn, core::ptr::null_mut()) };
+
+#[cfg(feature = "per_thread")]
+#[thread_local]
+static THREAD_HANDLER: AtomicPtr<ContextHandler> = AtomicPtr::new(core::ptr::null_mut());
+
+#[inline]
+fn handler_for(vector: u64) -> &'static ContextHandler {
+    #[cfg(feature = "per_thread")]
+    {
+        let handler = THREAD_HANDLER.load(Ordering::Acquire);
+        if !handler.is_null() {
+            // Only pointers to `ContextHandler`s with static lifetime are stored.
+            return unsafe { &*handler };
+        }
+    }
+    let handler = VECTOR_HANDLERS
+        .get(vector as usize)
+        .map(|slot| slot.load(Ordering::Acquire))
+        .filter(|handler| !handler.is_null())
+        .unwrap_or_else(|| DEFAULT_HANDLER.load(Ordering::Acquire));
+    // Only pointers to `ContextHandler`s with static lifetime are stored.
+    unsafe { &*handler }
+}
+
+/// Called by the trampoline with the XSAVE area of `size` bytes at `area`.
+///
+/// # Safety
+///
+/// `area` and `size` must satisfy the requirements of [`XSaveArea::from_raw`].
+#[cfg(feature = "xsave_full")]
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn uintr_handler_rust_entry(
+    utf: &mut UintrTrapframe,
+    area: *mut u8,
+    size: usize,
+) {
+    altstack::check_nested(utf);
+    let mut xsave = unsafe { XSaveArea::from_raw(area, size) };
+    handler_for(utf.info.uirr_vector).call(utf, &mut xsave);
+    crate::wait::mark_delivered();
+}
+
+#[cfg(not(feature = "xsave_full"))]
 #[unsafe(no_mangle)]
 pub extern "C" fn uintr_handler_rust_entry(utf: &mut UintrTrapframe) {
+    altstack::check_nested(utf);
     cfg_if::cfg_if! {
         if #[cfg(feature = "fp_simd")] {
             // only save legacy xstate to save stack space and reduce latency
             let mut fxstate = XSaveLegacy::default();
             unsafe { core::arch::x86_64::_fxsave64(&mut fxstate as *mut _ as *mut u8) };
-            HANDLER.load(Ordering::SeqCst).0(utf, &mut fxstate);
+            handler_for(utf.info.uirr_vector).call(utf, &mut fxstate);
             unsafe { core::arch::x86_64::_fxrstor64(&fxstate as *const _ as *const u8);}
         } else {
-            HANDLER.load(Ordering::SeqCst).0(utf);
+            handler_for(utf.info.uirr_vector).call(utf);
         }
     };
+    crate::wait::mark_delivered();
 }
 
+/// The address of the trampoline.
+///
+/// With the `xsave_full` feature, this also sizes the XSAVE area of the
+/// trampoline for the state components enabled in XCR0.
+///
+/// # Panics
+///
+/// With the `xsave_full` feature, panics if the operating system has not
+/// enabled XSAVE, i.e., CR4.OSXSAVE is clear.
 #[allow(dead_code)]
 pub fn handler_entry_addr() -> usize {
+    #[cfg(feature = "xsave_full")]
+    xstate::init();
     uintr_handler_asm_entry as usize
 }
 
+/// Install the default handler, which is called for vectors without a
+/// handler registered via [`register_vector_handler`].
 #[allow(dead_code)]
-pub fn set_handler(handler: UintrHandler) {
-    HANDLER.store(handler, Ordering::SeqCst);
+pub fn set_handler(handler: impl Into<Handler>) {
+    let adapter = match handler.into() {
+        Handler::Fn(handler) => {
+            HANDLER.store(handler, Ordering::SeqCst);
+            &DEFAULT_FN_ADAPTER
+        }
+        Handler::Context(handler) => handler,
+    };
+    DEFAULT_HANDLER.store(adapter as *const _ as *mut _, Ordering::SeqCst);
+}
+
+/// Install `handler` for user interrupts with `vector`, returning the
+/// handler previously registered for it.
+///
+/// # Panics
+///
+/// Panics if `vector` is not below [`NUM_VECTORS`].
+pub fn register_vector_handler(vector: u8, handler: impl Into<Handler>) -> Option<Handler> {
+    let vector = vector as usize;
+    let (adapter, previous_fn) = match handler.into() {
+        Handler::Fn(handler) => (
+            &VECTOR_FN_ADAPTERS[vector],
+            VECTOR_FNS[vector].swap(handler, Ordering::SeqCst),
+        ),
+        Handler::Context(handler) => (handler, VECTOR_FNS[vector].load(Ordering::SeqCst)),
+    };
+    let previous = VECTOR_HANDLERS[vector].swap(adapter as *const _ as *mut _, Ordering::AcqRel);
+    registered_handler(vector, previous, previous_fn)
+}
+
+/// Remove the handler for `vector`, so that the default handler is called
+/// again, and return it.
+///
+/// # Panics
+///
+/// Panics if `vector` is not below [`NUM_VECTORS`].
+pub fn unregister_vector_handler(vector: u8) -> Option<Handler> {
+    let vector = vector as usize;
+    let previous = VECTOR_HANDLERS[vector].swap(core::ptr::null_mut(), Ordering::AcqRel);
+    registered_handler(vector, previous, VECTOR_FNS[vector].load(Ordering::SeqCst))
+}
+
+fn registered_handler(
+    vector: usize,
+    handler: *mut ContextHandler,
+    handler_fn: UintrHandler,
+) -> Option<Handler> {
+    if handler.is_null() {
+        None
+    } else if core::ptr::eq(handler, &VECTOR_FN_ADAPTERS[vector]) {
+        Some(Handler::Fn(handler_fn))
+    } else {
+        Some(Handler::Context(unsafe { &*handler }))
+    }
+}
+
+/// Install `handler` for all user interrupts delivered to the calling thread,
+/// returning the handler previously installed for it.
+///
+/// A thread handler takes precedence over the handlers registered with
+/// [`register_vector_handler`] and [`set_handler`], so that each receiving
+/// thread can handle its user interrupts independently.
+#[cfg(feature = "per_thread")]
+pub fn set_thread_handler(handler: impl Into<Handler>) -> Option<Handler> {
+    let (adapter, previous_fn) = match handler.into() {
+        Handler::Fn(handler) => (
+            &THREAD_FN_ADAPTER,
+            THREAD_FN.swap(handler, Ordering::SeqCst),
+        ),
+        Handler::Context(handler) => (handler, THREAD_FN.load(Ordering::SeqCst)),
+    };
+    let previous = THREAD_HANDLER.swap(adapter as *const _ as *mut _, Ordering::AcqRel);
+    thread_handler(previous, previous_fn)
+}
+
+/// Remove the handler of the calling thread, so that the shared handlers are
+/// called again, and return it.
+#[cfg(feature = "per_thread")]
+pub fn clear_thread_handler() -> Option<Handler> {
+    let previous = THREAD_HANDLER.swap(core::ptr::null_mut(), Ordering::AcqRel);
+    thread_handler(previous, THREAD_FN.load(Ordering::SeqCst))
+}
+
+#[cfg(feature = "per_thread")]
+fn thread_handler(handler: *mut ContextHandler, handler_fn: UintrHandler) -> Option<Handler> {
+    if handler.is_null() {
+        None
+    } else if core::ptr::eq(handler, &THREAD_FN_ADAPTER) {
+        Some(Handler::Fn(handler_fn))
+    } else {
+        Some(Handler::Context(unsafe { &*handler }))
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    extern crate std;
+
+    use core::sync::atomic::AtomicU64;
+    use std::sync::{Mutex, MutexGuard};
+
+    use super::*;
+    use crate::kernel::{FakeError, FakeKernel, FakeVector, UintrKernel};
+
+    static LOCK: Mutex<()> = Mutex::new(());
+
+    /// Serialize the tests that install handlers or deliver user interrupts,
+    /// since handlers are shared by all threads.
+    pub(crate) fn lock() -> MutexGuard<'static, ()> {
+        LOCK.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    // Vectors seen by each handler, one bit per vector.
+    static FN_SEEN: AtomicU64 = AtomicU64::new(0);
+    static CONTEXT_SEEN: AtomicU64 = AtomicU64::new(0);
+    static DEFAULT_SEEN: AtomicU64 = AtomicU64::new(0);
+
+    fn record(seen: &AtomicU64, utf: &UintrTrapframe) {
+        seen.fetch_or(1 << utf.info.uirr_vector, Ordering::SeqCst);
+    }
+
+    cfg_if::cfg_if! {
+        if #[cfg(feature = "xsave_full")] {
+            fn fn_handler(utf: &mut UintrTrapframe, _: &mut XSaveArea) {
+                record(&FN_SEEN, utf);
+            }
+        } else if #[cfg(feature = "fp_simd")] {
+            fn fn_handler(utf: &mut UintrTrapframe, _: &mut XSaveLegacy) {
+                record(&FN_SEEN, utf);
+            }
+        } else {
+            fn fn_handler(utf: &mut UintrTrapframe) {
+                record(&FN_SEEN, utf);
+            }
+        }
+    }
+
+    static CONTEXT: ContextHandler =
+        ContextHandler::from_trapframe_fn(|utf| record(&CONTEXT_SEEN, utf));
+    static DEFAULT: ContextHandler =
+        ContextHandler::from_trapframe_fn(|utf| record(&DEFAULT_SEEN, utf));
+
+    fn is_fn_handler(handler: Option<Handler>) -> bool {
+        matches!(handler, Some(Handler::Fn(f)) if f.0 as usize == fn_handler as usize)
+    }
+
+    fn is_context(handler: Option<Handler>) -> bool {
+        matches!(handler, Some(Handler::Context(h)) if core::ptr::eq(h, &CONTEXT))
+    }
+
+    /// Send and deliver one user interrupt per vector.
+    fn deliver(kernel: &FakeKernel, vectors: &[u8]) {
+        for &vector in vectors {
+            let vector = kernel.create_vector(vector).unwrap();
+            let index = kernel.register_sender(&vector).unwrap();
+            kernel.send(index).unwrap();
+        }
+        assert_eq!(kernel.deliver(), Ok(vectors.len() as u32));
+        // Release the vectors and UITT entries for the next round.
+        for (index, &vector) in vectors.iter().enumerate() {
+            kernel.unregister_sender(&FakeVector(vector)).unwrap();
+            assert_eq!(kernel.send(index as u64), Err(FakeError::NoSender));
+        }
+        kernel.unregister_handler().unwrap();
+        unsafe { kernel.register_handler(handler_entry_addr()) }.unwrap();
+    }
+
+    fn take_seen() -> [u64; 3] {
+        [&FN_SEEN, &CONTEXT_SEEN, &DEFAULT_SEEN].map(|seen| seen.swap(0, Ordering::SeqCst))
+    }
+
+    #[test]
+    fn vector_handlers() {
+        let _lock = lock();
+        let kernel = FakeKernel::new();
+        crate::kernel::register_handler(&kernel, &DEFAULT).unwrap();
+        take_seen();
+
+        assert!(register_vector_handler(5, UintrHandler(fn_handler)).is_none());
+        assert!(register_vector_handler(6, &CONTEXT).is_none());
+        crate::wait::take_delivered();
+        deliver(&kernel, &[5, 6, 7]);
+        assert_eq!(take_seen(), [1 << 5, 1 << 6, 1 << 7]);
+        assert!(crate::wait::take_delivered());
+        assert!(!crate::wait::take_delivered());
+
+        // Swap the handlers of vectors 5 and 6.
+        assert!(is_fn_handler(register_vector_handler(5, &CONTEXT)));
+        assert!(is_context(register_vector_handler(
+            6,
+            UintrHandler(fn_handler)
+        )));
+        deliver(&kernel, &[5, 6]);
+        assert_eq!(take_seen(), [1 << 6, 1 << 5, 0]);
+
+        // Fall back to the default handler.
+        assert!(is_context(unregister_vector_handler(5)));
+        assert!(is_fn_handler(unregister_vector_handler(6)));
+        assert!(unregister_vector_handler(6).is_none());
+        deliver(&kernel, &[5, 6]);
+        assert_eq!(take_seen(), [0, 0, 1 << 5 | 1 << 6]);
+
+        set_handler(NOOP_HANDLER);
+        deliver(&kernel, &[5]);
+        assert_eq!(take_seen(), [0, 0, 0]);
+    }
 }
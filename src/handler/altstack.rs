@@ -0,0 +1,132 @@
+//! Alternate stack for user-interrupt delivery, similar to `sigaltstack`.
+
+use core::{
+    arch::asm,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use super::UintrTrapframe;
+use crate::msr::stack_adjust_load;
+
+// Bounds of the alternate stack of the receiver. `ALT_STACK_TOP` is 0 if no
+// alternate stack is in use. With the `per_thread` feature, every receiving
+// thread has its own alternate stack.
+#[cfg_attr(feature = "per_thread", thread_local)]
+static ALT_STACK_BASE: AtomicUsize = AtomicUsize::new(0);
+#[cfg_attr(feature = "per_thread", thread_local)]
+static ALT_STACK_TOP: AtomicUsize = AtomicUsize::new(0);
+
+/// A stack region onto which user interrupts are delivered, selected by
+/// loading UISTACKADJUST with [`AltStack::stack_adjust`].
+///
+/// Every delivery starts at the top of the region, so a handler running on
+/// it must not set UIF, e.g., with STUI: a nested delivery would overwrite
+/// the frame of the outer handler. In debug builds, [`stui`] panics when
+/// called on the alternate stack, and so do the functions of this crate that
+/// set UIF through it.
+///
+/// [`stui`]: crate::instructions::stui
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AltStack {
+    base: usize,
+    top: usize,
+}
+
+impl AltStack {
+    /// Use `stack` as alternate stack.
+    pub fn new(stack: &'static mut [u8]) -> Self {
+        unsafe { Self::from_raw(stack.as_mut_ptr(), stack.len()) }
+    }
+
+    /// Use the `size` bytes at `base` as alternate stack.
+    ///
+    /// # Safety
+    ///
+    /// The region must be valid for reads and writes, and not be used for
+    /// anything else, as long as deliveries may use it.
+    pub unsafe fn from_raw(base: *mut u8, size: usize) -> Self {
+        let base = base as usize;
+        Self {
+            base,
+            top: (base + size) & !0xf,
+        }
+    }
+
+    /// Lowest address of the region.
+    pub fn base(&self) -> usize {
+        self.base
+    }
+
+    /// The 16-byte aligned address below which frames are pushed.
+    pub fn top(&self) -> usize {
+        self.top
+    }
+
+    pub fn contains(&self, addr: usize) -> bool {
+        (self.base..self.top).contains(&addr)
+    }
+
+    /// UISTACKADJUST value in Load mode that delivers user interrupts onto
+    /// this stack, e.g., for
+    /// [`UintrState::set_stack_adjust`](crate::state::UintrState::set_stack_adjust).
+    pub fn stack_adjust(&self) -> u64 {
+        stack_adjust_load(self.top as u64)
+    }
+}
+
+/// Declare the alternate stack onto which UISTACKADJUST delivers the user
+/// interrupts of the receiver, or `None` if it does not use one, and return
+/// the previous declaration.
+///
+/// This does not program UISTACKADJUST, which is up to the kernel.
+pub fn set_alt_stack(stack: Option<AltStack>) -> Option<AltStack> {
+    let previous = alt_stack();
+    ALT_STACK_TOP.store(0, Ordering::SeqCst);
+    if let Some(stack) = stack {
+        ALT_STACK_BASE.store(stack.base, Ordering::SeqCst);
+        ALT_STACK_TOP.store(stack.top, Ordering::SeqCst);
+    }
+    previous
+}
+
+/// The alternate stack declared with [`set_alt_stack`].
+pub fn alt_stack() -> Option<AltStack> {
+    let top = ALT_STACK_TOP.load(Ordering::SeqCst);
+    (top != 0).then(|| AltStack {
+        base: ALT_STACK_BASE.load(Ordering::SeqCst),
+        top,
+    })
+}
+
+/// Whether the caller runs on the alternate stack, i.e., in a handler that
+/// was delivered onto it.
+pub fn on_alt_stack() -> bool {
+    let rsp: usize;
+    unsafe { asm!("mov {}, rsp", out(reg) rsp, options(nomem, nostack, preserves_flags)) };
+    alt_stack().is_some_and(|stack| stack.contains(rsp))
+}
+
+/// Panic in debug builds if the caller runs on the alternate stack, where
+/// setting UIF would allow a nested delivery.
+#[inline]
+pub(crate) fn debug_assert_off_alt_stack() {
+    debug_assert!(
+        !on_alt_stack(),
+        "user interrupts enabled on the alternate stack"
+    );
+}
+
+/// Panic if `utf` was delivered onto the alternate stack while a handler was
+/// already running on it.
+///
+/// This only detects the nested delivery after the fact: its frame has
+/// already overwritten the one of the outer handler, which cannot return
+/// anymore. The panic cannot unwind out of the handler entry, so it aborts.
+pub(super) fn check_nested(utf: &UintrTrapframe) {
+    let nested = alt_stack().is_some_and(|stack| {
+        stack.contains(utf as *const _ as usize) && stack.contains(utf.info.rsp as usize)
+    });
+    if nested {
+        panic!("nested user-interrupt delivery on the alternate stack");
+    }
+}
@@ -0,0 +1,199 @@
+//! Extended state saved around the handler with XSAVE (`xsave_full` feature).
+
+use core::{
+    arch::asm,
+    fmt::{self, Debug, Formatter},
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+};
+use x86::cpuid::native_cpuid::cpuid_count;
+use xsave::{XSaveHeader, XSaveLegacy};
+
+/// Size of the legacy region and the XSAVE header.
+const XSAVE_HEADER_END: usize = 576;
+
+// Read by the trampoline, the mask before the size. The defaults cover x87
+// and SSE state, which every processor with XSAVE enables, until the mask is
+// configured. `XSAVE_SIZE` must always be large enough for `XSAVE_MASK`, so it
+// only ever grows.
+pub(super) static XSAVE_SIZE: AtomicU64 = AtomicU64::new(XSAVE_HEADER_END as u64);
+pub(super) static XSAVE_MASK: AtomicU64 = AtomicU64::new(0b11);
+static CONFIGURED: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum XSaveMaskError {
+    /// The operating system has not enabled XSAVE, i.e., CR4.OSXSAVE is clear.
+    Unsupported,
+    /// The mask contains these state components, which are not enabled in XCR0.
+    NotEnabled(u64),
+    /// The mask lacks x87 or SSE state, which compiler-generated code in the
+    /// handler may modify.
+    MissingLegacy,
+}
+
+fn os_xsave() -> bool {
+    cpuid_count(1, 0).ecx & (1 << 27) != 0
+}
+
+fn xgetbv(index: u32) -> u64 {
+    let (eax, edx): (u32, u32);
+    unsafe {
+        asm!(
+            "xgetbv",
+            in("ecx") index,
+            out("eax") eax,
+            out("edx") edx,
+            options(nomem, nostack, preserves_flags)
+        )
+    };
+    (edx as u64) << 32 | eax as u64
+}
+
+/// Size of an XSAVE area in the standard format holding the components in
+/// `mask`.
+fn area_size(mask: u64) -> u64 {
+    (2..64)
+        .filter(|i| mask & (1 << i) != 0)
+        .map(|i| {
+            let component = cpuid_count(0xd, i);
+            component.ebx as u64 + component.eax as u64
+        })
+        .fold(XSAVE_HEADER_END as u64, u64::max)
+}
+
+fn apply(mask: u64) -> u64 {
+    // Grow the area before saving more components.
+    XSAVE_SIZE.fetch_max(area_size(mask), Ordering::SeqCst);
+    XSAVE_MASK.swap(mask, Ordering::SeqCst)
+}
+
+/// Save all state components enabled in XCR0, unless the mask has been
+/// configured with [`set_xsave_mask`].
+///
+/// Panics if the operating system has not enabled XSAVE, since the
+/// trampoline would raise #UD on every delivery.
+pub(super) fn init() {
+    assert!(
+        os_xsave(),
+        "the xsave_full trampoline requires CR4.OSXSAVE to be set"
+    );
+    if !CONFIGURED.swap(true, Ordering::SeqCst) {
+        apply(xgetbv(0));
+    }
+}
+
+/// Select the state components saved and restored around the handler, as a
+/// bitmap of XCR0 components (see [`xsave::XStateBv`]), and return the
+/// previous selection.
+///
+/// By default, all components enabled in XCR0 are saved. Components that are
+/// not selected are neither saved nor restored, so the handler must not
+/// modify them. For example, `0b111` saves x87, SSE and AVX state but no
+/// AVX-512 or AMX state. x87 and SSE state, i.e., `0b11`, must always be
+/// selected.
+///
+/// The area on the handler stack is sized for the largest selection made so
+/// far, so the mask should be selected before the handler is installed.
+pub fn set_xsave_mask(mask: u64) -> Result<u64, XSaveMaskError> {
+    if !os_xsave() {
+        return Err(XSaveMaskError::Unsupported);
+    }
+    if mask & 0b11 != 0b11 {
+        return Err(XSaveMaskError::MissingLegacy);
+    }
+    let not_enabled = mask & !xgetbv(0);
+    if not_enabled != 0 {
+        return Err(XSaveMaskError::NotEnabled(not_enabled));
+    }
+    CONFIGURED.store(true, Ordering::SeqCst);
+    Ok(apply(mask))
+}
+
+/// The state components currently saved and restored around the handler.
+pub fn xsave_mask() -> u64 {
+    XSAVE_MASK.load(Ordering::SeqCst)
+}
+
+/// The extended state of the interrupted code, saved with XSAVE in the
+/// standard format and restored with XRSTOR after the handler returns.
+///
+/// Changes made through this area are visible to the interrupted code.
+pub struct XSaveArea {
+    area: *mut u8,
+    size: usize,
+}
+
+impl XSaveArea {
+    /// # Safety
+    ///
+    /// `area` must be 64-byte aligned and point to `size` bytes, at least 576,
+    /// that are valid for reads and writes while the `XSaveArea` is alive.
+    pub unsafe fn from_raw(area: *mut u8, size: usize) -> Self {
+        debug_assert!(area as usize & 63 == 0 && size >= XSAVE_HEADER_END);
+        Self { area, size }
+    }
+
+    /// Size of the area in bytes.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        unsafe { core::slice::from_raw_parts(self.area, self.size) }
+    }
+
+    pub fn as_bytes_mut(&mut self) -> &mut [u8] {
+        unsafe { core::slice::from_raw_parts_mut(self.area, self.size) }
+    }
+
+    /// x87 and SSE state.
+    pub fn legacy(&self) -> &XSaveLegacy {
+        unsafe { &*(self.area as *const XSaveLegacy) }
+    }
+
+    pub fn legacy_mut(&mut self) -> &mut XSaveLegacy {
+        unsafe { &mut *(self.area as *mut XSaveLegacy) }
+    }
+
+    pub fn header(&self) -> &XSaveHeader {
+        unsafe { &*(self.area.add(512) as *const XSaveHeader) }
+    }
+
+    pub fn header_mut(&mut self) -> &mut XSaveHeader {
+        unsafe { &mut *(self.area.add(512) as *mut XSaveHeader) }
+    }
+
+    /// The saved state component `index`, for components above SSE.
+    ///
+    /// Returns `None` if the component is in its initial configuration, i.e.,
+    /// its bit in XSTATE_BV is clear, or if it is not part of this area.
+    pub fn component(&self, index: u32) -> Option<&[u8]> {
+        let (offset, size) = self.component_range(index)?;
+        Some(&self.as_bytes()[offset..offset + size])
+    }
+
+    pub fn component_mut(&mut self, index: u32) -> Option<&mut [u8]> {
+        let (offset, size) = self.component_range(index)?;
+        Some(&mut self.as_bytes_mut()[offset..offset + size])
+    }
+
+    fn component_range(&self, index: u32) -> Option<(usize, usize)> {
+        let bit = 1u64.checked_shl(index)?;
+        if index < 2 || self.header().xstate_bv.bits() & bit == 0 {
+            return None;
+        }
+        let component = cpuid_count(0xd, index);
+        let (size, offset) = (component.eax as usize, component.ebx as usize);
+        (size != 0 && offset >= XSAVE_HEADER_END && offset + size <= self.size)
+            .then_some((offset, size))
+    }
+}
+
+impl Debug for XSaveArea {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("XSaveArea")
+            .field("size", &self.size)
+            .field("legacy", self.legacy())
+            .field("header", self.header())
+            .finish()
+    }
+}
@@ -1,4 +1,8 @@
-use core::arch::asm;
+use core::{
+    arch::asm,
+    marker::PhantomData,
+    sync::atomic::{Ordering, compiler_fence},
+};
 
 /// User-Interrupt Return
 ///
@@ -47,8 +51,13 @@ pub fn clui() {
 }
 
 /// Set User Interrupt Flag
+///
+/// With the `handler` feature, this panics in debug builds when called on
+/// the alternate stack, see `handler::AltStack`.
 #[inline]
 pub fn stui() {
+    #[cfg(feature = "handler")]
+    crate::handler::debug_assert_off_alt_stack();
     unsafe {
         asm!(
             ".byte 0xf3",
@@ -75,6 +84,79 @@ pub fn enable_uirqs() {
     stui();
 }
 
+/// Disables user interrupts while alive, and restores the previous UIF when
+/// dropped, so that nested guards do not enable user interrupts early.
+#[must_use]
+pub struct UintrGuard {
+    enabled: bool,
+    // UIF belongs to the current thread.
+    _not_send: PhantomData<*const ()>,
+}
+
+impl UintrGuard {
+    #[inline]
+    pub fn new() -> Self {
+        // A user interrupt delivered in between returns with UIF set, so
+        // `enabled` stays correct.
+        let enabled = testui();
+        clui();
+        compiler_fence(Ordering::SeqCst);
+        Self {
+            enabled,
+            _not_send: PhantomData,
+        }
+    }
+
+    /// Whether user interrupts were enabled when the guard was created.
+    pub fn was_enabled(&self) -> bool {
+        self.enabled
+    }
+}
+
+impl Drop for UintrGuard {
+    #[inline]
+    fn drop(&mut self) {
+        compiler_fence(Ordering::SeqCst);
+        if self.enabled {
+            stui();
+        }
+    }
+}
+
+/// Run `f` with user interrupts disabled.
+#[inline]
+pub fn without_uirqs<R>(f: impl FnOnce() -> R) -> R {
+    let _guard = UintrGuard::new();
+    f()
+}
+
+/// Critical sections that disable user interrupts, like those of
+/// single-core embedded targets.
+///
+/// This is only sound if all data protected by critical sections is accessed
+/// by a single thread and its user-interrupt handler.
+#[cfg(feature = "critical_section_single_thread")]
+mod single_thread_critical_section {
+    struct UintrCriticalSection;
+    critical_section::set_impl!(UintrCriticalSection);
+
+    unsafe impl critical_section::Impl for UintrCriticalSection {
+        unsafe fn acquire() -> critical_section::RawRestoreState {
+            let enabled = super::testui();
+            super::clui();
+            core::sync::atomic::compiler_fence(core::sync::atomic::Ordering::SeqCst);
+            enabled
+        }
+
+        unsafe fn release(enabled: critical_section::RawRestoreState) {
+            core::sync::atomic::compiler_fence(core::sync::atomic::Ordering::SeqCst);
+            if enabled {
+                super::stui();
+            }
+        }
+    }
+}
+
 /// Send User Interprocessor Interrupt
 ///
 /// The SENDUIPI instruction sends the user interprocessor interrupt (IPI)
@@ -0,0 +1,323 @@
+//! Kernel-agnostic registration of user-interrupt receivers and senders.
+//!
+//! User-space runtimes program against [`UintrKernel`] instead of a specific
+//! kernel ABI. [`FakeKernel`] implements it in-process for tests.
+
+use core::{
+    cell::Cell,
+    fmt::{self, Debug, Formatter},
+};
+
+/// User-interrupt services provided by a kernel to user space.
+pub trait UintrKernel {
+    type Error: Debug;
+    /// A receiver's vector that senders connect to, e.g., a file descriptor.
+    type Vector;
+
+    /// Make the calling thread a receiver whose user interrupts are
+    /// delivered to `handler_addr`.
+    ///
+    /// # Safety
+    ///
+    /// `handler_addr` must be the address of code that handles a user
+    /// interrupt frame and returns with UIRET.
+    unsafe fn register_handler(&self, handler_addr: usize) -> Result<(), Self::Error>;
+
+    /// Stop receiving user interrupts on the calling thread.
+    fn unregister_handler(&self) -> Result<(), Self::Error>;
+
+    /// Create a handle through which senders post user interrupts with
+    /// `vector` to the calling thread.
+    fn create_vector(&self, vector: u8) -> Result<Self::Vector, Self::Error>;
+
+    /// Allocate an entry in the calling thread's UITT targeting `vector`,
+    /// and return its index.
+    fn register_sender(&self, vector: &Self::Vector) -> Result<u64, Self::Error>;
+
+    /// Release the UITT entry targeting `vector`.
+    fn unregister_sender(&self, vector: &Self::Vector) -> Result<(), Self::Error>;
+
+    /// Block until a user interrupt is delivered to the calling thread.
+    ///
+    /// Implementations must also return if a user interrupt is pending while
+    /// UIF is clear, which is how `wait::wait_kernel` (`handler` feature)
+    /// avoids missing user interrupts.
+    fn wait(&self) -> Result<(), Self::Error>;
+}
+
+/// Install `handler` with [`set_handler`] and register this crate's handler
+/// trampoline with `kernel`.
+///
+/// [`set_handler`]: crate::handler::set_handler
+#[cfg(feature = "handler")]
+pub fn register_handler<K: UintrKernel>(
+    kernel: &K,
+    handler: impl Into<crate::handler::Handler>,
+) -> Result<(), K::Error> {
+    crate::handler::set_handler(handler);
+    unsafe { kernel.register_handler(crate::handler::handler_entry_addr()) }
+}
+
+/// Install `handler` for the calling thread with [`set_thread_handler`] and
+/// register this crate's handler trampoline with `kernel`.
+///
+/// [`set_thread_handler`]: crate::handler::set_thread_handler
+#[cfg(feature = "per_thread")]
+pub fn register_thread_handler<K: UintrKernel>(
+    kernel: &K,
+    handler: impl Into<crate::handler::Handler>,
+) -> Result<(), K::Error> {
+    crate::handler::set_thread_handler(handler);
+    unsafe { kernel.register_handler(crate::handler::handler_entry_addr()) }
+}
+
+#[cfg(feature = "linux")]
+impl UintrKernel for crate::linux::LinuxUintr {
+    type Error = crate::linux::Errno;
+    type Vector = crate::linux::UintrFd;
+
+    unsafe fn register_handler(&self, handler_addr: usize) -> Result<(), Self::Error> {
+        unsafe { crate::linux::LinuxUintr::register_handler(self, handler_addr, 0) }
+    }
+
+    fn unregister_handler(&self) -> Result<(), Self::Error> {
+        crate::linux::LinuxUintr::unregister_handler(self, 0)
+    }
+
+    fn create_vector(&self, vector: u8) -> Result<Self::Vector, Self::Error> {
+        self.vector_fd(vector as u64, 0)
+    }
+
+    fn register_sender(&self, vector: &Self::Vector) -> Result<u64, Self::Error> {
+        crate::linux::LinuxUintr::register_sender(self, *vector, 0)
+    }
+
+    fn unregister_sender(&self, vector: &Self::Vector) -> Result<(), Self::Error> {
+        crate::linux::LinuxUintr::unregister_sender(self, *vector, 0)
+    }
+
+    fn wait(&self) -> Result<(), Self::Error> {
+        match crate::linux::LinuxUintr::wait(self, 0) {
+            // The kernel reports the delivery as interruption.
+            Err(crate::linux::Errno::EINTR) => Ok(()),
+            result => result,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FakeError {
+    /// No handler is registered.
+    NoHandler,
+    /// The vector exceeds 63.
+    InvalidVector,
+    /// The vector has already been created.
+    VectorInUse,
+    /// All UITT entries are in use.
+    UittFull,
+    /// No sender is registered for the vector or UITT index.
+    NoSender,
+    /// [`UintrKernel::wait`] would block because no user interrupt is pending.
+    WouldBlock,
+}
+
+/// A receiver's vector of [`FakeKernel`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FakeVector(pub u8);
+
+const FAKE_UITT_SIZE: usize = 64;
+
+/// In-process implementation of [`UintrKernel`] with a single receiver,
+/// which is also the only sender.
+///
+/// User interrupts sent with [`FakeKernel::send`] become pending. With the
+/// `handler` feature, `FakeKernel::deliver` hands them to the handlers
+/// installed with `handler::set_handler` and the like.
+pub struct FakeKernel {
+    handler_addr: Cell<Option<usize>>,
+    vectors: Cell<u64>,
+    uitt: [Cell<Option<u8>>; FAKE_UITT_SIZE],
+    pending: Cell<u64>,
+}
+
+impl FakeKernel {
+    pub const fn new() -> Self {
+        Self {
+            handler_addr: Cell::new(None),
+            vectors: Cell::new(0),
+            uitt: [const { Cell::new(None) }; FAKE_UITT_SIZE],
+            pending: Cell::new(0),
+        }
+    }
+
+    /// The address passed to [`UintrKernel::register_handler`], if any.
+    pub fn handler_addr(&self) -> Option<usize> {
+        self.handler_addr.get()
+    }
+
+    /// Post a user interrupt through UITT entry `uitt_index`, like SENDUIPI.
+    pub fn send(&self, uitt_index: u64) -> Result<(), FakeError> {
+        let vector = self
+            .uitt
+            .get(uitt_index as usize)
+            .and_then(Cell::get)
+            .ok_or(FakeError::NoSender)?;
+        self.pending.set(self.pending.get() | (1 << vector));
+        Ok(())
+    }
+
+    /// The vectors of pending user interrupts, one bit per vector.
+    pub fn pending(&self) -> u64 {
+        self.pending.get()
+    }
+
+    /// Clear and return the pending user interrupts.
+    pub fn take_pending(&self) -> u64 {
+        self.pending.replace(0)
+    }
+
+    /// Call the Rust handler of this crate once for each pending user
+    /// interrupt, highest vector first, as user-interrupt delivery would.
+    ///
+    /// Returns the number of delivered user interrupts.
+    #[cfg(feature = "handler")]
+    pub fn deliver(&self) -> Result<u32, FakeError> {
+        if self.handler_addr.get().is_none() {
+            return Err(FakeError::NoHandler);
+        }
+        let mut delivered = 0;
+        while self.pending.get() != 0 {
+            let vector = 63 - self.pending.get().leading_zeros() as u64;
+            self.pending.set(self.pending.get() & !(1 << vector));
+            let mut tf = crate::handler::UintrTrapframe::default();
+            tf.info.uirr_vector = vector;
+            cfg_if::cfg_if! {
+                if #[cfg(feature = "xsave_full")] {
+                    // The handler sees the initial configuration of all
+                    // extended state components.
+                    let mut area = xsave::XSave::default();
+                    unsafe {
+                        crate::handler::uintr_handler_rust_entry(
+                            &mut tf,
+                            &mut area as *mut _ as *mut u8,
+                            core::mem::size_of_val(&area),
+                        )
+                    };
+                } else {
+                    crate::handler::uintr_handler_rust_entry(&mut tf);
+                }
+            }
+            delivered += 1;
+        }
+        Ok(delivered)
+    }
+}
+
+impl UintrKernel for FakeKernel {
+    type Error = FakeError;
+    type Vector = FakeVector;
+
+    unsafe fn register_handler(&self, handler_addr: usize) -> Result<(), Self::Error> {
+        self.handler_addr.set(Some(handler_addr));
+        Ok(())
+    }
+
+    fn unregister_handler(&self) -> Result<(), Self::Error> {
+        self.handler_addr.take().ok_or(FakeError::NoHandler)?;
+        self.vectors.set(0);
+        self.pending.set(0);
+        Ok(())
+    }
+
+    fn create_vector(&self, vector: u8) -> Result<Self::Vector, Self::Error> {
+        if self.handler_addr.get().is_none() {
+            return Err(FakeError::NoHandler);
+        }
+        if vector >= 64 {
+            return Err(FakeError::InvalidVector);
+        }
+        if self.vectors.get() & (1 << vector) != 0 {
+            return Err(FakeError::VectorInUse);
+        }
+        self.vectors.set(self.vectors.get() | (1 << vector));
+        Ok(FakeVector(vector))
+    }
+
+    fn register_sender(&self, vector: &Self::Vector) -> Result<u64, Self::Error> {
+        let index = self
+            .uitt
+            .iter()
+            .position(|entry| entry.get().is_none())
+            .ok_or(FakeError::UittFull)?;
+        self.uitt[index].set(Some(vector.0));
+        Ok(index as u64)
+    }
+
+    fn unregister_sender(&self, vector: &Self::Vector) -> Result<(), Self::Error> {
+        let entry = self
+            .uitt
+            .iter()
+            .find(|entry| entry.get() == Some(vector.0))
+            .ok_or(FakeError::NoSender)?;
+        entry.set(None);
+        Ok(())
+    }
+
+    fn wait(&self) -> Result<(), Self::Error> {
+        if self.pending.get() == 0 {
+            Err(FakeError::WouldBlock)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl Debug for FakeKernel {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("FakeKernel")
+            .field("handler_addr", &self.handler_addr.get())
+            .field("vectors", &format_args!("{:#x}", self.vectors.get()))
+            .field("pending", &format_args!("{:#x}", self.pending.get()))
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let kernel = FakeKernel::new();
+        assert_eq!(kernel.create_vector(3), Err(FakeError::NoHandler));
+        unsafe { kernel.register_handler(0x1000) }.unwrap();
+        assert_eq!(kernel.handler_addr(), Some(0x1000));
+
+        let vector = kernel.create_vector(3).unwrap();
+        assert_eq!(kernel.create_vector(3), Err(FakeError::VectorInUse));
+        assert_eq!(kernel.create_vector(64), Err(FakeError::InvalidVector));
+        let index = kernel.register_sender(&vector).unwrap();
+        assert_eq!(kernel.wait(), Err(FakeError::WouldBlock));
+
+        kernel.send(index).unwrap();
+        assert_eq!(kernel.pending(), 1 << 3);
+        assert_eq!(kernel.wait(), Ok(()));
+        #[cfg(feature = "handler")]
+        {
+            let _lock = crate::handler::tests::lock();
+            crate::wait::take_delivered();
+            assert_eq!(kernel.deliver(), Ok(1));
+            assert!(crate::wait::take_delivered());
+        }
+        #[cfg(not(feature = "handler"))]
+        assert_eq!(kernel.take_pending(), 1 << 3);
+        assert_eq!(kernel.pending(), 0);
+
+        kernel.unregister_sender(&vector).unwrap();
+        assert_eq!(kernel.send(index), Err(FakeError::NoSender));
+        assert_eq!(kernel.unregister_sender(&vector), Err(FakeError::NoSender));
+        kernel.unregister_handler().unwrap();
+        assert_eq!(kernel.unregister_handler(), Err(FakeError::NoHandler));
+        assert_eq!(kernel.handler_addr(), None);
+    }
+}
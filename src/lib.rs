@@ -1,12 +1,33 @@
 #![no_std]
 #![feature(naked_functions)]
+#![cfg_attr(feature = "per_thread", feature(thread_local))]
 #![doc = include_str!("../README.md")]
 
+pub mod channel;
+pub mod cpu;
+pub mod deferred;
+pub mod detect;
 pub mod instructions;
+pub mod kernel;
 pub mod msr;
+pub mod sender;
 pub mod state;
+pub mod uitt;
 pub mod uitte;
 pub mod upid;
 
+#[cfg(feature = "alloc")]
+extern crate alloc;
+#[cfg(feature = "std")]
+extern crate std;
+
+#[cfg(feature = "async")]
+pub mod asynch;
+#[cfg(feature = "emulator")]
+pub mod emulator;
 #[cfg(feature = "handler")]
 pub mod handler;
+#[cfg(feature = "linux")]
+pub mod linux;
+#[cfg(feature = "handler")]
+pub mod wait;
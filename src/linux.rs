@@ -0,0 +1,140 @@
+//! Bindings for the proposed Linux uintr system calls.
+//!
+//! The system calls are not part of mainline Linux, and patched kernels do
+//! not agree on their numbers, so they are configurable via
+//! [`SyscallNumbers`].
+
+use core::arch::asm;
+
+/// An error number returned by a failed system call.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Errno(pub i32);
+
+impl Errno {
+    pub const EINTR: Errno = Errno(4);
+    pub const EINVAL: Errno = Errno(22);
+    pub const ENOSYS: Errno = Errno(38);
+}
+
+/// A file descriptor referring to a receiver's user-interrupt vector.
+pub type UintrFd = i32;
+
+/// System call numbers of the uintr system calls on x86_64.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SyscallNumbers {
+    pub register_handler: usize,
+    pub unregister_handler: usize,
+    pub vector_fd: usize,
+    pub register_sender: usize,
+    pub unregister_sender: usize,
+    pub wait: usize,
+}
+
+impl SyscallNumbers {
+    /// Numbers used by the first RFC of the uintr patches, where the vector
+    /// fd system call was named `uintr_create_fd`. Note that mainline Linux
+    /// has since assigned these numbers to other system calls, e.g.,
+    /// `futex_waitv` and `map_shadow_stack`.
+    pub const RFC_V1: SyscallNumbers = SyscallNumbers {
+        register_handler: 449,
+        unregister_handler: 450,
+        vector_fd: 451,
+        register_sender: 452,
+        unregister_sender: 453,
+        wait: 454,
+    };
+}
+
+#[inline]
+unsafe fn syscall2(nr: usize, arg0: usize, arg1: usize) -> Result<usize, Errno> {
+    let ret: isize;
+    unsafe {
+        asm!(
+            "syscall",
+            inlateout("rax") nr as isize => ret,
+            in("rdi") arg0,
+            in("rsi") arg1,
+            lateout("rcx") _,
+            lateout("r11") _,
+            options(nostack)
+        )
+    };
+    if (-4095..0).contains(&ret) {
+        Err(Errno(-ret as i32))
+    } else {
+        Ok(ret as usize)
+    }
+}
+
+/// The uintr system calls of a Linux kernel.
+#[derive(Debug, Clone, Copy)]
+pub struct LinuxUintr {
+    nr: SyscallNumbers,
+}
+
+impl LinuxUintr {
+    /// Invoke the uintr system calls with the numbers `nr`.
+    ///
+    /// # Safety
+    ///
+    /// `nr` must be the numbers of the uintr system calls of the running
+    /// kernel. Other system calls would be invoked with arbitrary arguments
+    /// otherwise.
+    pub const unsafe fn new(nr: SyscallNumbers) -> Self {
+        Self { nr }
+    }
+
+    pub fn syscall_numbers(&self) -> &SyscallNumbers {
+        &self.nr
+    }
+
+    /// `uintr_register_handler`: make the calling thread a receiver whose
+    /// user interrupts are delivered to `handler_addr`.
+    ///
+    /// # Safety
+    ///
+    /// `handler_addr` must be the address of code that handles a user
+    /// interrupt frame and returns with UIRET.
+    pub unsafe fn register_handler(&self, handler_addr: usize, flags: u32) -> Result<(), Errno> {
+        unsafe { syscall2(self.nr.register_handler, handler_addr, flags as usize) }.map(|_| ())
+    }
+
+    /// `uintr_register_handler` with the entry of this crate's handler
+    /// trampoline, see [`handler_entry_addr`](crate::handler::handler_entry_addr).
+    #[cfg(feature = "handler")]
+    pub fn register_entry(&self, flags: u32) -> Result<(), Errno> {
+        unsafe { self.register_handler(crate::handler::handler_entry_addr(), flags) }
+    }
+
+    /// `uintr_unregister_handler`: stop receiving user interrupts on the
+    /// calling thread.
+    pub fn unregister_handler(&self, flags: u32) -> Result<(), Errno> {
+        unsafe { syscall2(self.nr.unregister_handler, flags as usize, 0) }.map(|_| ())
+    }
+
+    /// `uintr_vector_fd`: create a file descriptor that senders use to post
+    /// user interrupts with `vector` to the calling thread.
+    pub fn vector_fd(&self, vector: u64, flags: u32) -> Result<UintrFd, Errno> {
+        unsafe { syscall2(self.nr.vector_fd, vector as usize, flags as usize) }
+            .map(|fd| fd as UintrFd)
+    }
+
+    /// `uintr_register_sender`: allocate an entry in the calling thread's
+    /// UITT targeting the receiver behind `fd`, and return its index.
+    pub fn register_sender(&self, fd: UintrFd, flags: u32) -> Result<u64, Errno> {
+        unsafe { syscall2(self.nr.register_sender, fd as usize, flags as usize) }
+            .map(|index| index as u64)
+    }
+
+    /// `uintr_unregister_sender`: release the UITT entry targeting the
+    /// receiver behind `fd`.
+    pub fn unregister_sender(&self, fd: UintrFd, flags: u32) -> Result<(), Errno> {
+        unsafe { syscall2(self.nr.unregister_sender, fd as usize, flags as usize) }.map(|_| ())
+    }
+
+    /// `uintr_wait`: block until a user interrupt is delivered to the calling
+    /// thread. The kernel reports the delivery with [`Errno::EINTR`].
+    pub fn wait(&self, flags: u32) -> Result<(), Errno> {
+        unsafe { syscall2(self.nr.wait, flags as usize, 0) }.map(|_| ())
+    }
+}
@@ -2,6 +2,8 @@ use core::fmt::Debug;
 use tock_registers::{LocalRegisterCopy, fields::FieldValue, register_bitfields};
 use x86::msr::{rdmsr, wrmsr};
 
+use crate::instructions::{disable_uirqs, enable_uirqs, uirqs_enabled};
+
 /// User Interrupts support
 pub const X86_FEATURE_UINTR: u32 = 18 * 32 + 5;
 /// enable User Interrupts support
@@ -22,6 +24,16 @@ pub enum UintrMsr {
 }
 
 impl UintrMsr {
+    /// All user-interrupt MSRs.
+    pub const ALL: [UintrMsr; 6] = [
+        UintrMsr::IA32_UINTR_RR,
+        UintrMsr::IA32_UINTR_HANDLER,
+        UintrMsr::IA32_UINTR_STACKADJUST,
+        UintrMsr::IA32_UINTR_MISC,
+        UintrMsr::IA32_UINTR_PD,
+        UintrMsr::IA32_UINTR_TT,
+    ];
+
     /// Read 64 bits msr register.
     #[inline(always)]
     pub fn read(self) -> u64 {
@@ -40,6 +52,111 @@ impl UintrMsr {
     }
 }
 
+/// Backend through which the user-interrupt state is read from and written to
+/// a logical processor.
+///
+/// UIF is not an MSR, but it is part of the user-interrupt state component
+/// and is saved and restored together with IA32_UINTR_MISC.
+pub trait MsrAccess {
+    /// Read 64 bits msr register.
+    fn read(&self, msr: UintrMsr) -> u64;
+
+    /// Write 64 bits to msr register.
+    ///
+    /// # Safety
+    ///
+    /// The caller must ensure that this write operation has no unsafe side
+    /// effects.
+    unsafe fn write(&mut self, msr: UintrMsr, value: u64);
+
+    /// Read the user-interrupt flag.
+    fn uif(&self) -> bool;
+
+    /// Set or clear the user-interrupt flag.
+    fn set_uif(&mut self, enabled: bool);
+}
+
+/// The MSRs and UIF of the current logical processor.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct HardwareMsr;
+
+impl MsrAccess for HardwareMsr {
+    #[inline(always)]
+    fn read(&self, msr: UintrMsr) -> u64 {
+        msr.read()
+    }
+
+    #[inline(always)]
+    unsafe fn write(&mut self, msr: UintrMsr, value: u64) {
+        unsafe { msr.write(value) }
+    }
+
+    #[inline(always)]
+    fn uif(&self) -> bool {
+        uirqs_enabled()
+    }
+
+    #[inline(always)]
+    fn set_uif(&mut self, enabled: bool) {
+        if enabled {
+            enable_uirqs();
+        } else {
+            disable_uirqs();
+        }
+    }
+}
+
+/// In-memory MSR backend for testing code built on [`MsrAccess`] without
+/// UINTR hardware.
+///
+/// Like WRMSR, writing a value with reserved bits set to IA32_UINTR_MISC
+/// (bits 63:40, including the UIF bit of the xstate layout) panics instead of
+/// raising #GP.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct FakeMsr {
+    values: [u64; 6],
+    uif: bool,
+}
+
+impl FakeMsr {
+    /// All MSRs are 0 and UIF is clear.
+    pub const fn new() -> Self {
+        Self {
+            values: [0; 6],
+            uif: false,
+        }
+    }
+
+    #[inline]
+    fn index(msr: UintrMsr) -> usize {
+        (msr as u32 - UintrMsr::IA32_UINTR_RR as u32) as usize
+    }
+}
+
+impl MsrAccess for FakeMsr {
+    fn read(&self, msr: UintrMsr) -> u64 {
+        self.values[Self::index(msr)]
+    }
+
+    unsafe fn write(&mut self, msr: UintrMsr, value: u64) {
+        if let UintrMsr::IA32_UINTR_MISC = msr {
+            assert!(
+                value >> 40 == 0,
+                "#GP: reserved bits set in IA32_UINTR_MISC: {value:#x}"
+            );
+        }
+        self.values[Self::index(msr)] = value;
+    }
+
+    fn uif(&self) -> bool {
+        self.uif
+    }
+
+    fn set_uif(&mut self, enabled: bool) {
+        self.uif = enabled;
+    }
+}
+
 register_bitfields! [u64,
     /// UISTACKADJUST: user-interrupt stack adjustment.
     /// This value controls adjustment to the stack pointer (RSP) prior to
@@ -104,6 +221,24 @@ register_bitfields! [u64,
     ]
 ];
 
+/// Size in bytes of the red zone below RSP that the System V x86-64 ABI
+/// allows functions to use without adjusting RSP.
+pub const SYSV_RED_ZONE: u64 = 128;
+
+/// UISTACKADJUST value in Subtract mode that makes user-interrupt delivery
+/// skip `red_zone` bytes below the interrupted RSP, e.g., [`SYSV_RED_ZONE`].
+///
+/// The value is rounded up to a multiple of 16, which also keeps bit 0 clear.
+pub const fn stack_adjust_subtract(red_zone: u64) -> u64 {
+    (red_zone + 15) & !15
+}
+
+/// UISTACKADJUST value in Load mode that makes user-interrupt delivery load
+/// RSP with `stack_top`, before aligning it to 16 bytes.
+pub const fn stack_adjust_load(stack_top: u64) -> u64 {
+    (stack_top & StackAdjust::ADDR::SET.mask()) | StackAdjust::MODE::Load.value
+}
+
 pub type StackAdjustLocal = LocalRegisterCopy<u64, StackAdjust::Register>;
 pub type StackAdjustFieldValue = FieldValue<u64, StackAdjust::Register>;
 pub type StackAdjustMode = StackAdjust::MODE::Value;
@@ -0,0 +1,133 @@
+//! Checked SENDUIPI
+
+use crate::{
+    instructions::send_uipi,
+    state::UintrState,
+    uitt::{Slot, Uitt, UittFault, UittIndex, UittStorage, UittView},
+    uitte::UittEntry,
+};
+
+/// Reasons why a user IPI cannot be sent without SENDUIPI raising #GP.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SendError {
+    /// IA32_UINTR_TT\[0\] is clear.
+    SendDisabled,
+    /// The index exceeds UITTSZ.
+    IndexOutOfRange,
+    /// The valid bit of the UITT entry is clear.
+    InvalidEntry,
+    /// The UITT entry sets reserved bits.
+    ReservedBits,
+    /// The handle refers to a UITT entry that has been freed.
+    StaleIndex,
+}
+
+impl From<UittFault> for SendError {
+    fn from(fault: UittFault) -> Self {
+        match fault {
+            UittFault::SendDisabled => Self::SendDisabled,
+            UittFault::IndexOutOfRange => Self::IndexOutOfRange,
+            UittFault::InvalidEntry => Self::InvalidEntry,
+            UittFault::ReservedBits => Self::ReservedBits,
+        }
+    }
+}
+
+/// Sends user IPIs through a snapshot of the UITT installed in
+/// IA32_UINTR_TT, checking every index before executing SENDUIPI.
+///
+/// The snapshot must describe the table that is actually installed for the
+/// current thread, otherwise the checks do not reflect what SENDUIPI sees.
+#[derive(Debug, Clone, Copy)]
+pub struct UipiSender<'a> {
+    view: UittView<'a>,
+    slots: Option<&'a [Slot]>,
+}
+
+impl<'a> UipiSender<'a> {
+    /// Sender for `uitt`.
+    ///
+    /// # Safety
+    ///
+    /// `uitt` must be the table installed in IA32_UINTR_TT of the current
+    /// thread, with SEND_ENABLED set and UITTSZ equal to [`Uitt::uitt_sz`],
+    /// for as long as the sender is used.
+    pub unsafe fn from_uitt<S: UittStorage>(uitt: &'a Uitt<S>) -> Self {
+        Self {
+            view: uitt.view(),
+            slots: Some(uitt.slots()),
+        }
+    }
+
+    /// Sender for the UITT described by `state`.
+    ///
+    /// # Safety
+    ///
+    /// The caller must ensure that UITTADDR[0, UITTSZ] of `state` point to
+    /// valid UITT entries for `'a`.
+    pub unsafe fn from_state(state: &'a UintrState) -> Self {
+        Self {
+            view: unsafe { state.uitt() },
+            slots: None,
+        }
+    }
+
+    /// The entry SENDUIPI would use for `index`.
+    pub fn check(&self, index: u64) -> Result<&'a UittEntry, SendError> {
+        Ok(self.view.get(index)?)
+    }
+
+    /// Execute SENDUIPI with `index` if the entry passes [`UipiSender::check`].
+    pub fn send(&self, index: u64) -> Result<(), SendError> {
+        self.check(index)?;
+        // The index is within UITTSZ and refers to a valid, well-formed entry.
+        unsafe { send_uipi(index) };
+        Ok(())
+    }
+
+    /// Execute SENDUIPI with the entry referred to by `index`.
+    ///
+    /// For a sender built from a [`Uitt`], stale handles are rejected.
+    pub fn send_to(&self, index: UittIndex) -> Result<(), SendError> {
+        if let Some(slots) = self.slots {
+            match slots.get(index.index() as usize) {
+                Some(slot) if slot.is_live(index) => {}
+                _ => return Err(SendError::StaleIndex),
+            }
+        }
+        self.send(index.index())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::uitt::{ArrayStorage, FixedUitt};
+
+    #[test]
+    fn check() {
+        let mut uitt = FixedUitt::<4>::new();
+        let index = uitt.allocate(UittEntry::new(3, 0x1000)).unwrap();
+        let sender = unsafe { UipiSender::from_uitt(&uitt) };
+        assert_eq!(sender.check(0).unwrap().uintr_vector(), 3);
+        assert_eq!(sender.check(1).err(), Some(SendError::IndexOutOfRange));
+
+        uitt.free(index).unwrap();
+        let sender = unsafe { UipiSender::from_uitt(&uitt) };
+        assert_eq!(sender.check(0).err(), Some(SendError::InvalidEntry));
+        assert_eq!(sender.send_to(index), Err(SendError::StaleIndex));
+    }
+
+    #[test]
+    fn empty_table() {
+        let uitt = Uitt::<ArrayStorage<0>>::new();
+        let sender = unsafe { UipiSender::from_uitt(&uitt) };
+        assert_eq!(sender.check(0).err(), Some(SendError::IndexOutOfRange));
+        #[cfg(feature = "alloc")]
+        {
+            let uitt = Uitt::growable();
+            let sender = unsafe { UipiSender::from_uitt(&uitt) };
+            assert_eq!(sender.check(0).err(), Some(SendError::IndexOutOfRange));
+        }
+    }
+}
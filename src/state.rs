@@ -1,8 +1,4 @@
-use crate::{
-    instructions::{disable_uirqs, enable_uirqs, uirqs_enabled},
-    msr::*,
-    uitte::UittEntry,
-};
+use crate::{msr::*, uitt::UittView, uitte::UittEntry};
 use core::fmt::{Debug, Formatter, Result};
 
 use core::slice;
@@ -105,106 +101,162 @@ impl UintrState {
             .set(post_desc_addr & PostDesc::UPIDADDR::SET.mask());
     }
 
+    /// UISTACKADJUST, as built by [`stack_adjust_subtract`] or
+    /// [`stack_adjust_load`].
+    pub fn stack_adjust(&self) -> u64 {
+        self.stack_adjust.get()
+    }
+
+    /// Set UISTACKADJUST, e.g., to `stack_adjust_subtract(SYSV_RED_ZONE)`
+    /// so that user-interrupt delivery does not clobber the red zone of the
+    /// interrupted code. This function does not write to the MSRs.
+    pub fn set_stack_adjust(&mut self, stack_adjust: u64) {
+        self.stack_adjust.set(stack_adjust);
+    }
+
     /// Read UITT and UITTSZ from MSR
     #[inline]
     pub fn save_sender(&mut self) {
-        self.target_table.set(UintrMsr::IA32_UINTR_TT.read());
-        self.read_misc();
+        self.save_sender_with(&HardwareMsr);
     }
 
     /// Read handler, stack adjust, UINV, UIF, UPID, and UIRR from MSR
     #[inline]
     pub fn save_receiver(&mut self) {
-        self.handler.set(UintrMsr::IA32_UINTR_HANDLER.read());
-        self.stack_adjust
-            .set(UintrMsr::IA32_UINTR_STACKADJUST.read());
-        self.read_misc();
-        self.post_desc.set(UintrMsr::IA32_UINTR_PD.read());
-        self.uirr.set(UintrMsr::IA32_UINTR_RR.read());
+        self.save_receiver_with(&HardwareMsr);
     }
 
     /// Read all UINTR states from MSR
     #[inline]
     pub fn save_all(&mut self) {
-        self.handler.set(UintrMsr::IA32_UINTR_HANDLER.read());
+        self.save_all_with(&HardwareMsr);
+    }
+
+    /// Write UITT and UITTSZ to MSR
+    #[inline]
+    pub fn restore_sender(&self) {
+        self.restore_sender_with(&mut HardwareMsr);
+    }
+
+    /// Write handler, stack adjust, UINV, UIF, UPID, and UIRR to MSR
+    #[inline]
+    pub fn restore_receiver(&self) {
+        self.restore_receiver_with(&mut HardwareMsr);
+    }
+
+    /// Write all UINTR states to MSR
+    #[inline]
+    pub fn restore_all(&self) {
+        self.restore_all_with(&mut HardwareMsr);
+    }
+
+    /// Read UITT and UITTSZ through `msr`
+    #[inline]
+    pub fn save_sender_with<M: MsrAccess>(&mut self, msr: &M) {
+        self.target_table.set(msr.read(UintrMsr::IA32_UINTR_TT));
+        self.read_misc(msr);
+    }
+
+    /// Read handler, stack adjust, UINV, UIF, UPID, and UIRR through `msr`
+    #[inline]
+    pub fn save_receiver_with<M: MsrAccess>(&mut self, msr: &M) {
+        self.handler.set(msr.read(UintrMsr::IA32_UINTR_HANDLER));
         self.stack_adjust
-            .set(UintrMsr::IA32_UINTR_STACKADJUST.read());
-        self.read_misc();
-        self.post_desc.set(UintrMsr::IA32_UINTR_PD.read());
-        self.uirr.set(UintrMsr::IA32_UINTR_RR.read());
-        self.target_table.set(UintrMsr::IA32_UINTR_TT.read());
+            .set(msr.read(UintrMsr::IA32_UINTR_STACKADJUST));
+        self.read_misc(msr);
+        self.post_desc.set(msr.read(UintrMsr::IA32_UINTR_PD));
+        self.uirr.set(msr.read(UintrMsr::IA32_UINTR_RR));
     }
 
+    /// Read all UINTR states through `msr`
     #[inline]
-    fn read_misc(&mut self) {
-        self.misc.set(UintrMsr::IA32_UINTR_MISC.read());
-        self.misc.modify(Misc::UIF.val(uirqs_enabled() as u64));
+    pub fn save_all_with<M: MsrAccess>(&mut self, msr: &M) {
+        self.save_receiver_with(msr);
+        self.target_table.set(msr.read(UintrMsr::IA32_UINTR_TT));
     }
 
     #[inline]
-    fn write_misc(&self) {
-        if self.misc.is_set(Misc::UIF) {
-            enable_uirqs();
-        } else {
-            disable_uirqs();
-        }
+    fn read_misc<M: MsrAccess>(&mut self, msr: &M) {
+        self.misc.set(msr.read(UintrMsr::IA32_UINTR_MISC));
+        self.misc.modify(Misc::UIF.val(msr.uif() as u64));
+    }
+
+    #[inline]
+    fn write_misc<M: MsrAccess>(&self, msr: &mut M) {
+        msr.set_uif(self.misc.is_set(Misc::UIF));
         let mut misc_msr = self.misc;
         misc_msr.modify(Misc::UIF::CLEAR);
         unsafe {
-            UintrMsr::IA32_UINTR_MISC.write(misc_msr.get());
+            msr.write(UintrMsr::IA32_UINTR_MISC, misc_msr.get());
         }
     }
 
-    /// Write UITT and UITTSZ to MSR
+    /// Write UITT and UITTSZ through `msr`
     #[inline]
-    pub fn restore_sender(&self) {
-        self.write_misc();
+    pub fn restore_sender_with<M: MsrAccess>(&self, msr: &mut M) {
+        self.write_misc(msr);
         unsafe {
-            UintrMsr::IA32_UINTR_TT.write(self.target_table.get());
+            msr.write(UintrMsr::IA32_UINTR_TT, self.target_table.get());
         }
     }
 
-    /// Write handler, stack adjust, UINV, UIF, UPID, and UIRR to MSR
+    /// Write handler, stack adjust, UINV, UIF, UPID, and UIRR through `msr`
     #[inline]
-    pub fn restore_receiver(&self) {
-        self.write_misc();
+    pub fn restore_receiver_with<M: MsrAccess>(&self, msr: &mut M) {
+        self.write_misc(msr);
         unsafe {
-            UintrMsr::IA32_UINTR_HANDLER.write(self.handler.get());
-            UintrMsr::IA32_UINTR_STACKADJUST.write(self.stack_adjust.get());
-            UintrMsr::IA32_UINTR_PD.write(self.post_desc.get());
-            UintrMsr::IA32_UINTR_RR.write(self.uirr.get());
+            msr.write(UintrMsr::IA32_UINTR_HANDLER, self.handler.get());
+            msr.write(UintrMsr::IA32_UINTR_STACKADJUST, self.stack_adjust.get());
+            msr.write(UintrMsr::IA32_UINTR_PD, self.post_desc.get());
+            msr.write(UintrMsr::IA32_UINTR_RR, self.uirr.get());
         }
     }
 
-    /// Write all UINTR states to MSR
+    /// Write all UINTR states through `msr`
     #[inline]
-    pub fn restore_all(&self) {
-        self.write_misc();
+    pub fn restore_all_with<M: MsrAccess>(&self, msr: &mut M) {
+        self.restore_receiver_with(msr);
         unsafe {
-            UintrMsr::IA32_UINTR_HANDLER.write(self.handler.get());
-            UintrMsr::IA32_UINTR_STACKADJUST.write(self.stack_adjust.get());
-            UintrMsr::IA32_UINTR_PD.write(self.post_desc.get());
-            UintrMsr::IA32_UINTR_RR.write(self.uirr.get());
-            UintrMsr::IA32_UINTR_TT.write(self.target_table.get());
+            msr.write(UintrMsr::IA32_UINTR_TT, self.target_table.get());
         }
     }
 
+    /// UITTADDR, without the SEND_ENABLED bit.
+    pub fn uitt_addr(&self) -> u64 {
+        self.target_table.get() & TargetTable::UITTADDR::SET.mask()
+    }
+
+    /// UITTSZ, the highest index of a valid entry in the UITT.
+    pub fn uitt_sz(&self) -> u64 {
+        self.misc.read(Misc::UITTSZ)
+    }
+
+    pub fn is_sender_enabled(&self) -> bool {
+        self.target_table.is_set(TargetTable::SEND_ENABLED)
+    }
+
+    /// The UITTSZ+1 entries at UITTADDR. The view is empty if UITTADDR is 0.
+    ///
     /// # Safety
     ///
-    /// The caller must ensure that the UITTADDR[0, UITTSZ] point to valid
+    /// The caller must ensure that UITTADDR[0, UITTSZ] point to valid
     /// memory addresses containing UITT entries.
-    pub unsafe fn uitt(&self) -> &[UittEntry] {
-        let uitt_sz = self.misc.read(Misc::UITTSZ);
-        unsafe { slice::from_raw_parts(self.target_table.get() as *const _, uitt_sz as usize) }
+    pub unsafe fn uitt(&self) -> UittView<'_> {
+        unsafe { UittView::from_raw(self.uitt_addr(), self.uitt_sz(), self.is_sender_enabled()) }
     }
 
+    /// The UITTSZ+1 entries at UITTADDR. The slice is empty if UITTADDR is 0.
+    ///
     /// # Safety
     ///
-    /// The caller must ensure that the UITTADDR[0, UITTSZ] point to valid
+    /// The caller must ensure that UITTADDR[0, UITTSZ] point to valid
     /// memory addresses containing UITT entries.
     pub unsafe fn uitt_mut(&mut self) -> &mut [UittEntry] {
-        let uitt_sz = self.misc.read(Misc::UITTSZ);
-        unsafe { slice::from_raw_parts_mut(self.target_table.get() as *mut _, uitt_sz as usize) }
+        let uitt_addr = self.uitt_addr();
+        if uitt_addr == 0 {
+            return &mut [];
+        }
+        unsafe { slice::from_raw_parts_mut(uitt_addr as *mut _, self.uitt_sz() as usize + 1) }
     }
 }
 
@@ -234,14 +286,86 @@ impl Debug for UintrState {
                 "send_enabled",
                 &(self.target_table.is_set(TargetTable::SEND_ENABLED)),
             )
-            .field(
-                "UITT addr",
-                &format_args!(
-                    "{:#x}",
-                    self.target_table.get()
-                        & (TargetTable::UITTADDR.mask << TargetTable::UITTADDR.shift)
-                ),
-            )
+            .field("UITT addr", &format_args!("{:#x}", self.uitt_addr()))
             .finish()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(uif: bool) -> UintrState {
+        let mut state = UintrState::new(
+            0x7fff_1234_5670,
+            15,
+            true,
+            0x40_1000,
+            0x7fff_ffff_e000,
+            StackAdjustMode::Load,
+            0xec,
+            uif,
+            0x7fff_aaaa_bb40,
+        );
+        state.uirr.set(0x8000_0000_0000_0021);
+        state
+    }
+
+    fn assert_same(a: &UintrState, b: &UintrState) {
+        assert_eq!(a.handler.get(), b.handler.get());
+        assert_eq!(a.stack_adjust.get(), b.stack_adjust.get());
+        assert_eq!(a.misc.get(), b.misc.get());
+        assert_eq!(a.post_desc.get(), b.post_desc.get());
+        assert_eq!(a.uirr.get(), b.uirr.get());
+        assert_eq!(a.target_table.get(), b.target_table.get());
+    }
+
+    #[test]
+    fn round_trip() {
+        for uif in [false, true] {
+            let state = state(uif);
+            let mut msr = FakeMsr::new();
+            msr.set_uif(!uif);
+            state.restore_all_with(&mut msr);
+
+            // UIF goes to the flag, and is cleared in the MSR value.
+            assert_eq!(msr.uif(), uif);
+            let misc = msr.read(UintrMsr::IA32_UINTR_MISC);
+            assert_eq!(misc >> 40, 0);
+            assert_eq!(
+                misc,
+                Misc::UITTSZ.val(15).value | Misc::UINV.val(0xec).value
+            );
+
+            let mut saved = UintrState::default();
+            saved.save_all_with(&msr);
+            assert_same(&saved, &state);
+            assert_eq!(saved.misc.is_set(Misc::UIF), uif);
+            assert_eq!(saved.uitt_addr(), 0x7fff_1234_5670);
+            assert_eq!(saved.uitt_sz(), 15);
+            assert!(saved.is_sender_enabled());
+        }
+    }
+
+    #[test]
+    fn round_trip_sender_and_receiver() {
+        for uif in [false, true] {
+            let state = state(uif);
+            let mut msr = FakeMsr::new();
+            state.restore_sender_with(&mut msr);
+            state.restore_receiver_with(&mut msr);
+
+            let mut saved = UintrState::default();
+            saved.save_sender_with(&msr);
+            saved.save_receiver_with(&msr);
+            assert_same(&saved, &state);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "#GP")]
+    fn misc_reserved_bits() {
+        let mut msr = FakeMsr::new();
+        unsafe { msr.write(UintrMsr::IA32_UINTR_MISC, 1 << 63) };
+    }
+}
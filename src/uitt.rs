@@ -0,0 +1,452 @@
+//! UITT: User Interrupt Target Table management
+
+use core::fmt::{Debug, Formatter};
+
+use crate::uitte::UittEntry;
+
+/// Handle to an allocated UITT entry.
+///
+/// The generation detects handles that outlive the entry they were
+/// allocated for: once an entry is freed, all of its handles are stale,
+/// even if the index is allocated again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct UittIndex {
+    index: u32,
+    generation: u32,
+}
+
+impl UittIndex {
+    /// The operand of SENDUIPI.
+    pub fn index(&self) -> u64 {
+        self.index as u64
+    }
+
+    pub fn generation(&self) -> u32 {
+        self.generation
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UittError {
+    /// All entries are allocated and the storage cannot grow.
+    Full,
+    /// The handle refers to an entry that has been freed.
+    StaleIndex,
+}
+
+/// Conditions under which SENDUIPI raises #GP because of the UITT.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UittFault {
+    /// IA32_UINTR_TT\[0\] is clear.
+    SendDisabled,
+    /// The index exceeds UITTSZ.
+    IndexOutOfRange,
+    /// The valid bit of the entry is clear.
+    InvalidEntry,
+    /// The entry sets reserved bits.
+    ReservedBits,
+}
+
+/// The UITTSZ+1 entries of a user-interrupt target table, as seen by SENDUIPI.
+#[derive(Debug, Clone, Copy)]
+pub struct UittView<'a> {
+    entries: &'a [UittEntry],
+    send_enabled: bool,
+}
+
+impl<'a> UittView<'a> {
+    /// A view of `entries`, where UITTSZ is `entries.len() - 1`.
+    pub fn new(entries: &'a [UittEntry], send_enabled: bool) -> Self {
+        Self {
+            entries,
+            send_enabled,
+        }
+    }
+
+    /// Build a view from raw UITTADDR and UITTSZ values.
+    ///
+    /// A null `uitt_addr` yields an empty view.
+    ///
+    /// # Safety
+    ///
+    /// If `uitt_addr` is not null, it must point to `uitt_sz + 1` UITT
+    /// entries that are valid for `'a`.
+    pub unsafe fn from_raw(uitt_addr: u64, uitt_sz: u64, send_enabled: bool) -> Self {
+        let entries = if uitt_addr == 0 {
+            &[]
+        } else {
+            unsafe {
+                core::slice::from_raw_parts(uitt_addr as *const UittEntry, uitt_sz as usize + 1)
+            }
+        };
+        Self::new(entries, send_enabled)
+    }
+
+    pub fn entries(&self) -> &'a [UittEntry] {
+        self.entries
+    }
+
+    pub fn is_send_enabled(&self) -> bool {
+        self.send_enabled
+    }
+
+    /// The entry SENDUIPI would use for `index`, checked for the same
+    /// conditions under which SENDUIPI raises #GP.
+    pub fn get(&self, index: u64) -> Result<&'a UittEntry, UittFault> {
+        if !self.send_enabled {
+            return Err(UittFault::SendDisabled);
+        }
+        let entry = usize::try_from(index)
+            .ok()
+            .and_then(|index| self.entries.get(index))
+            .ok_or(UittFault::IndexOutOfRange)?;
+        if !entry.is_valid() {
+            return Err(UittFault::InvalidEntry);
+        }
+        if entry.validate().is_err() {
+            return Err(UittFault::ReservedBits);
+        }
+        Ok(entry)
+    }
+}
+
+const NO_SLOT: u32 = u32::MAX;
+
+/// Bookkeeping of one UITT entry.
+#[derive(Debug, Clone, Copy)]
+pub struct Slot {
+    generation: u32,
+    next_free: u32,
+    allocated: bool,
+}
+
+impl Slot {
+    /// Whether `index` is a live handle of this slot.
+    pub(crate) fn is_live(&self, index: UittIndex) -> bool {
+        self.allocated && self.generation == index.generation
+    }
+
+    pub const UNUSED: Slot = Slot {
+        generation: 0,
+        next_free: NO_SLOT,
+        allocated: false,
+    };
+}
+
+/// Memory backing a [`Uitt`]: the entries, which are read by SENDUIPI, and
+/// one [`Slot`] per entry.
+pub trait UittStorage {
+    fn entries(&self) -> &[UittEntry];
+    fn entries_mut(&mut self) -> &mut [UittEntry];
+    fn slots(&self) -> &[Slot];
+    fn slots_mut(&mut self) -> &mut [Slot];
+
+    /// Add more entries, initialized to [`UittEntry::INVALID`] and
+    /// [`Slot::UNUSED`]. Returns `false` if the capacity is fixed.
+    fn grow(&mut self) -> bool {
+        false
+    }
+}
+
+/// Fixed-capacity storage of `N` entries.
+pub struct ArrayStorage<const N: usize> {
+    entries: [UittEntry; N],
+    slots: [Slot; N],
+}
+
+impl<const N: usize> ArrayStorage<N> {
+    pub const fn new() -> Self {
+        Self {
+            entries: [UittEntry::INVALID; N],
+            slots: [Slot::UNUSED; N],
+        }
+    }
+}
+
+impl<const N: usize> UittStorage for ArrayStorage<N> {
+    fn entries(&self) -> &[UittEntry] {
+        &self.entries
+    }
+
+    fn entries_mut(&mut self) -> &mut [UittEntry] {
+        &mut self.entries
+    }
+
+    fn slots(&self) -> &[Slot] {
+        &self.slots
+    }
+
+    fn slots_mut(&mut self) -> &mut [Slot] {
+        &mut self.slots
+    }
+}
+
+/// Heap storage that doubles its capacity when full.
+#[cfg(feature = "alloc")]
+pub struct VecStorage {
+    entries: alloc::vec::Vec<UittEntry>,
+    slots: alloc::vec::Vec<Slot>,
+}
+
+#[cfg(feature = "alloc")]
+impl VecStorage {
+    pub const fn new() -> Self {
+        Self {
+            entries: alloc::vec::Vec::new(),
+            slots: alloc::vec::Vec::new(),
+        }
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl UittStorage for VecStorage {
+    fn entries(&self) -> &[UittEntry] {
+        &self.entries
+    }
+
+    fn entries_mut(&mut self) -> &mut [UittEntry] {
+        &mut self.entries
+    }
+
+    fn slots(&self) -> &[Slot] {
+        &self.slots
+    }
+
+    fn slots_mut(&mut self) -> &mut [Slot] {
+        &mut self.slots
+    }
+
+    fn grow(&mut self) -> bool {
+        let additional = self.entries.len().max(16);
+        self.entries
+            .resize_with(self.entries.len() + additional, || UittEntry::INVALID);
+        self.slots
+            .resize(self.slots.len() + additional, Slot::UNUSED);
+        true
+    }
+}
+
+/// A UITT with `N` entries that can be placed in a `static`.
+pub type FixedUitt<const N: usize> = Uitt<ArrayStorage<N>>;
+
+/// A UITT that grows on demand.
+///
+/// Growing moves the table, so [`Uitt::sender_config`] must be installed
+/// again whenever [`Uitt::as_ptr`] changes.
+#[cfg(feature = "alloc")]
+pub type GrowableUitt = Uitt<VecStorage>;
+
+/// User-interrupt target table with allocation of entries.
+///
+/// Freed entries are marked invalid and reused in LIFO order. The table must
+/// not be moved while its address is installed in IA32_UINTR_TT.
+pub struct Uitt<S: UittStorage> {
+    storage: S,
+    free_head: u32,
+    /// Slots at or above this index have never been allocated.
+    unused_from: u32,
+    len: u32,
+}
+
+impl<const N: usize> Uitt<ArrayStorage<N>> {
+    pub const fn new() -> Self {
+        Self::with_storage(ArrayStorage::new())
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl Uitt<VecStorage> {
+    /// An empty table that allocates its entries on the heap.
+    ///
+    /// This is not called `new`, so that `Uitt::new()` keeps inferring
+    /// [`ArrayStorage`] when the `alloc` feature is enabled.
+    pub const fn growable() -> Self {
+        Self::with_storage(VecStorage::new())
+    }
+}
+
+impl<S: UittStorage> Uitt<S> {
+    /// Manage `storage`, whose entries and slots must all be unused.
+    pub const fn with_storage(storage: S) -> Self {
+        Self {
+            storage,
+            free_head: NO_SLOT,
+            unused_from: 0,
+            len: 0,
+        }
+    }
+
+    /// Number of allocated entries.
+    pub fn len(&self) -> usize {
+        self.len as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.storage.entries().len()
+    }
+
+    /// The linear address of the table.
+    pub fn as_ptr(&self) -> *const UittEntry {
+        self.storage.entries().as_ptr()
+    }
+
+    /// All entries of the table, allocated or not.
+    pub fn entries(&self) -> &[UittEntry] {
+        self.storage.entries()
+    }
+
+    /// Store `entry` in a free slot and return its handle.
+    pub fn allocate(&mut self, entry: UittEntry) -> Result<UittIndex, UittError> {
+        let index = if self.free_head != NO_SLOT {
+            let index = self.free_head;
+            self.free_head = self.storage.slots()[index as usize].next_free;
+            index
+        } else {
+            if self.unused_from as usize == self.capacity() && !self.storage.grow() {
+                return Err(UittError::Full);
+            }
+            self.unused_from += 1;
+            self.unused_from - 1
+        };
+        let slot = &mut self.storage.slots_mut()[index as usize];
+        slot.allocated = true;
+        slot.next_free = NO_SLOT;
+        let generation = slot.generation;
+        self.storage.entries_mut()[index as usize] = entry;
+        self.len += 1;
+        Ok(UittIndex { index, generation })
+    }
+
+    /// Invalidate and release the entry referred to by `index`, returning
+    /// its last content.
+    pub fn free(&mut self, index: UittIndex) -> Result<UittEntry, UittError> {
+        self.check(index)?;
+        let i = index.index as usize;
+        let entry = core::mem::replace(&mut self.storage.entries_mut()[i], UittEntry::INVALID);
+        let slot = &mut self.storage.slots_mut()[i];
+        slot.allocated = false;
+        slot.generation = slot.generation.wrapping_add(1);
+        slot.next_free = self.free_head;
+        self.free_head = index.index;
+        self.len -= 1;
+        Ok(entry)
+    }
+
+    /// Whether `index` refers to an entry that is still allocated.
+    pub fn is_live(&self, index: UittIndex) -> bool {
+        self.check(index).is_ok()
+    }
+
+    pub fn get(&self, index: UittIndex) -> Result<&UittEntry, UittError> {
+        self.check(index)?;
+        Ok(&self.storage.entries()[index.index as usize])
+    }
+
+    pub fn get_mut(&mut self, index: UittIndex) -> Result<&mut UittEntry, UittError> {
+        self.check(index)?;
+        Ok(&mut self.storage.entries_mut()[index.index as usize])
+    }
+
+    /// UITTSZ: the highest index of an allocated entry, or 0 if the table is
+    /// empty.
+    pub fn uitt_sz(&self) -> u64 {
+        let slots = &self.storage.slots()[..self.unused_from as usize];
+        slots.iter().rposition(|slot| slot.allocated).unwrap_or(0) as u64
+    }
+
+    /// The entries `0..=UITTSZ`, as seen by SENDUIPI once this table is
+    /// installed with sending enabled. The view is empty if the table has no
+    /// storage.
+    pub fn view(&self) -> UittView<'_> {
+        let entries = self.storage.entries();
+        if entries.is_empty() {
+            return UittView::new(entries, true);
+        }
+        UittView::new(&entries[..self.uitt_sz() as usize + 1], true)
+    }
+
+    /// The `(uitt_addr, uitt_sz)` pair describing this table, as expected by
+    /// [`UintrState::set_sender`](crate::state::UintrState::set_sender), or
+    /// `None` if the table has no storage yet, e.g., an empty `GrowableUitt`.
+    ///
+    /// UITTSZ is 0 for an empty table, so SENDUIPI still reads entry 0,
+    /// which is invalid until allocated.
+    pub fn sender_config(&self) -> Option<(u64, u64)> {
+        (self.capacity() != 0).then(|| (self.as_ptr() as u64, self.uitt_sz()))
+    }
+
+    pub(crate) fn slots(&self) -> &[Slot] {
+        self.storage.slots()
+    }
+
+    fn check(&self, index: UittIndex) -> Result<(), UittError> {
+        match self.storage.slots().get(index.index as usize) {
+            Some(slot) if slot.is_live(index) => Ok(()),
+            _ => Err(UittError::StaleIndex),
+        }
+    }
+}
+
+impl<S: UittStorage> Debug for Uitt<S> {
+    fn fmt(&self, f: &mut Formatter) -> core::fmt::Result {
+        f.debug_struct("Uitt")
+            .field("addr", &self.as_ptr())
+            .field("UITTSZ", &self.uitt_sz())
+            .field("len", &self.len)
+            .field("capacity", &self.capacity())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sender_config() {
+        let mut uitt = FixedUitt::<4>::new();
+        assert_eq!(uitt.sender_config(), Some((uitt.as_ptr() as u64, 0)));
+        uitt.allocate(UittEntry::new(1, 0x1000)).unwrap();
+        let index = uitt.allocate(UittEntry::new(2, 0x1000)).unwrap();
+        assert_eq!(uitt.sender_config(), Some((uitt.as_ptr() as u64, 1)));
+        uitt.free(index).unwrap();
+        assert_eq!(uitt.sender_config(), Some((uitt.as_ptr() as u64, 0)));
+
+        assert_eq!(Uitt::<ArrayStorage<0>>::new().sender_config(), None);
+    }
+
+    #[test]
+    fn view() {
+        let mut uitt = FixedUitt::<4>::new();
+        assert_eq!(uitt.view().entries().len(), 1);
+        assert_eq!(uitt.view().get(0).err(), Some(UittFault::InvalidEntry));
+        uitt.allocate(UittEntry::new(1, 0x1000)).unwrap();
+        uitt.allocate(UittEntry::new(2, 0x1000)).unwrap();
+        let view = uitt.view();
+        assert_eq!(view.entries().len(), 2);
+        assert_eq!(view.get(1).unwrap().uintr_vector(), 2);
+        assert_eq!(view.get(2).err(), Some(UittFault::IndexOutOfRange));
+    }
+
+    #[test]
+    fn empty_view() {
+        let uitt = Uitt::<ArrayStorage<0>>::new();
+        assert!(uitt.view().entries().is_empty());
+        assert_eq!(uitt.view().get(0).err(), Some(UittFault::IndexOutOfRange));
+        #[cfg(feature = "alloc")]
+        assert!(Uitt::growable().view().entries().is_empty());
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn growable_sender_config() {
+        let mut uitt = Uitt::growable();
+        assert_eq!(uitt.sender_config(), None);
+        uitt.allocate(UittEntry::new(1, 0x1000)).unwrap();
+        assert_eq!(uitt.sender_config(), Some((uitt.as_ptr() as u64, 0)));
+    }
+}
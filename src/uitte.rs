@@ -3,6 +3,7 @@
 use core::fmt::{Debug, Formatter, Result};
 
 use crate::msr::{PostDesc, PostDescLocal};
+use bitflags::bitflags;
 use tock_registers::{LocalRegisterCopy, register_bitfields, register_structs};
 
 register_bitfields![u64,
@@ -14,6 +15,22 @@ register_bitfields![u64,
 
 pub type VuvLocal = LocalRegisterCopy<u64, VUV::Register>;
 
+bitflags! {
+    /// Rules of the UITT entry format violated by an entry.
+    /// SENDUIPI raises #GP when using an entry that violates any of them.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct UittEntryViolations: u32 {
+        /// Reserved bits 7:1 of the VUV word are set.
+        const RESERVED_VUV_LOW = 1 << 0;
+        /// Reserved bits 63:14 of the VUV word are set.
+        const RESERVED_VUV_HIGH = 1 << 1;
+        /// UPIDADDR is not 64-byte aligned.
+        const UPID_MISALIGNED = 1 << 2;
+        /// The user-interrupt vector exceeds 63.
+        const VECTOR_OUT_OF_RANGE = 1 << 3;
+    }
+}
+
 register_structs! {
     #[repr(C, align(16))]
     pub UittEntry {
@@ -24,6 +41,12 @@ register_structs! {
 }
 
 impl UittEntry {
+    /// An entry with the valid bit clear.
+    pub const INVALID: UittEntry = UittEntry {
+        state: VuvLocal::new(0),
+        upid_addr: PostDescLocal::new(0),
+    };
+
     pub fn new(uintr_vector: u8, upid_addr: u64) -> Self {
         Self {
             state: VuvLocal::new(
@@ -33,6 +56,46 @@ impl UittEntry {
         }
     }
 
+    /// Like [`UittEntry::new`], but rejects a vector or UPID address that
+    /// would be silently truncated.
+    pub fn try_new(
+        uintr_vector: u8,
+        upid_addr: u64,
+    ) -> core::result::Result<Self, UittEntryViolations> {
+        let mut violations = UittEntryViolations::empty();
+        violations.set(
+            UittEntryViolations::VECTOR_OUT_OF_RANGE,
+            uintr_vector as u64 > VUV::UINTR_VECTOR.mask,
+        );
+        violations.set(
+            UittEntryViolations::UPID_MISALIGNED,
+            upid_addr & !PostDesc::UPIDADDR::SET.mask() != 0,
+        );
+        if violations.is_empty() {
+            Ok(Self::new(uintr_vector, upid_addr))
+        } else {
+            Err(violations)
+        }
+    }
+
+    /// Check the raw content of the entry, e.g., of a table supplied by user
+    /// space, against the UITT entry format.
+    pub fn validate(&self) -> core::result::Result<(), UittEntryViolations> {
+        let vuv = self.state.get();
+        let mut violations = UittEntryViolations::empty();
+        violations.set(UittEntryViolations::RESERVED_VUV_LOW, vuv & 0xfe != 0);
+        violations.set(UittEntryViolations::RESERVED_VUV_HIGH, vuv >> 14 != 0);
+        violations.set(
+            UittEntryViolations::UPID_MISALIGNED,
+            self.upid_addr.get() & !PostDesc::UPIDADDR::SET.mask() != 0,
+        );
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(violations)
+        }
+    }
+
     pub fn is_valid(&self) -> bool {
         self.state.read(VUV::VALID) == 1
     }
@@ -44,6 +107,10 @@ impl UittEntry {
     pub fn uintr_vector(&self) -> u64 {
         self.state.read(VUV::UINTR_VECTOR)
     }
+
+    pub fn upid_addr(&self) -> u64 {
+        self.upid_addr.get()
+    }
 }
 
 impl Debug for UittEntry {
@@ -1,11 +1,15 @@
 //! UPID: User Posted-Interrupt Descriptor
 
-use core::fmt::{Debug, Formatter, Result};
+use core::{
+    fmt::{Debug, Formatter, Result},
+    sync::atomic::{AtomicU64, Ordering},
+};
 
+use bitflags::bitflags;
 use tock_registers::{LocalRegisterCopy, register_bitfields};
 
 register_bitfields![u64,
-    NotificationControl [
+    pub NotificationControl [
         /// If this bit is set, there is a notification outstanding for one or
         /// more user interrupts in PIR.
         OUTSTANDING OFFSET(0) NUMBITS(1),
@@ -23,6 +27,85 @@ register_bitfields![u64,
 
 pub type NotificationControlLocal = LocalRegisterCopy<u64, NotificationControl::Register>;
 
+bitflags! {
+    /// Rules of the UPID format violated by a descriptor.
+    ///
+    /// SENDUIPI raises #GP when posting to a UPID that violates one of the
+    /// [`UpidViolations::RESERVED`] rules. It accepts illegal notification
+    /// vectors, which [`Upid::validate`] reports as well.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct UpidViolations: u32 {
+        /// Reserved bits 15:2 of the notification control word are set.
+        const RESERVED_CONTROL_LOW = 1 << 0;
+        /// Reserved bits 31:24 of the notification control word are set.
+        const RESERVED_CONTROL_HIGH = 1 << 1;
+        /// The notification vector is one of the vectors 0-15, which the
+        /// local APIC treats as illegal.
+        const ILLEGAL_NOTIFICATION_VECTOR = 1 << 2;
+    }
+}
+
+impl UpidViolations {
+    /// The violations for which SENDUIPI raises #GP.
+    pub const RESERVED: Self = Self::RESERVED_CONTROL_LOW.union(Self::RESERVED_CONTROL_HIGH);
+}
+
+/// Local APIC operating mode, which determines the DESTINATION encoding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApicMode {
+    XApic,
+    X2Apic,
+}
+
+/// Physical APIC ID of the logical processor that receives notifications.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ApicId {
+    /// 8-bit APIC ID, stored in bits 47:40 of the UPID.
+    XApic(u8),
+    /// 32-bit APIC ID, stored in bits 63:32 of the UPID.
+    X2Apic(u32),
+}
+
+impl ApicId {
+    pub const fn xapic(id: u8) -> Self {
+        Self::XApic(id)
+    }
+
+    pub const fn x2apic(id: u32) -> Self {
+        Self::X2Apic(id)
+    }
+
+    pub const fn mode(self) -> ApicMode {
+        match self {
+            Self::XApic(_) => ApicMode::XApic,
+            Self::X2Apic(_) => ApicMode::X2Apic,
+        }
+    }
+
+    pub const fn id(self) -> u32 {
+        match self {
+            Self::XApic(id) => id as u32,
+            Self::X2Apic(id) => id,
+        }
+    }
+
+    /// Encode as the value of the DESTINATION field.
+    pub const fn to_destination(self) -> u32 {
+        match self {
+            Self::XApic(id) => (id as u32) << 8,
+            Self::X2Apic(id) => id,
+        }
+    }
+
+    /// Decode the value of the DESTINATION field.
+    pub const fn from_destination(destination: u32, mode: ApicMode) -> Self {
+        match mode {
+            ApicMode::XApic => Self::XApic((destination >> 8) as u8),
+            ApicMode::X2Apic => Self::X2Apic(destination),
+        }
+    }
+}
+
 #[repr(C, align(64))]
 pub struct Upid {
     pub control: NotificationControlLocal,
@@ -32,18 +115,74 @@ pub struct Upid {
 }
 
 impl Upid {
-    pub fn new(outstanding: bool, suppressed: bool, notif_vector: u8, destination: u32) -> Self {
+    pub fn new(outstanding: bool, suppressed: bool, notif_vector: u8, destination: ApicId) -> Self {
         Self {
             control: NotificationControlLocal::new(
                 NotificationControl::OUTSTANDING.val(outstanding as _).value
                     | NotificationControl::SUPPRESSED.val(suppressed as _).value
                     | NotificationControl::VECTOR.val(notif_vector as _).value
-                    | NotificationControl::DESTINATION.val(destination as _).value,
+                    | NotificationControl::DESTINATION
+                        .val(destination.to_destination() as _)
+                        .value,
             ),
             posted_uirq: LocalRegisterCopy::new(0),
         }
     }
 
+    /// Like [`Upid::new`], but rejects notification vectors that the local
+    /// APIC cannot deliver.
+    pub fn try_new(
+        outstanding: bool,
+        suppressed: bool,
+        notif_vector: u8,
+        destination: ApicId,
+    ) -> core::result::Result<Self, UpidViolations> {
+        let upid = Self::new(outstanding, suppressed, notif_vector, destination);
+        upid.validate()?;
+        Ok(upid)
+    }
+
+    /// Check the raw content of the descriptor, e.g., of a UPID supplied by
+    /// user space, against the UPID format.
+    ///
+    /// Notification vectors 0-15 are reported although SENDUIPI itself
+    /// accepts them, because the resulting notification would be dropped.
+    /// See [`Upid::validate_reserved`] for the checks of SENDUIPI.
+    pub fn validate(&self) -> core::result::Result<(), UpidViolations> {
+        let mut violations = self.reserved_violations();
+        violations.set(
+            UpidViolations::ILLEGAL_NOTIFICATION_VECTOR,
+            self.control.read(NotificationControl::VECTOR) < 16,
+        );
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(violations)
+        }
+    }
+
+    /// Check the reserved bits of the notification control word, which
+    /// SENDUIPI raises #GP for if any of them is set.
+    pub fn validate_reserved(&self) -> core::result::Result<(), UpidViolations> {
+        let violations = self.reserved_violations();
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(violations)
+        }
+    }
+
+    fn reserved_violations(&self) -> UpidViolations {
+        let control = self.control.get();
+        let mut violations = UpidViolations::empty();
+        violations.set(UpidViolations::RESERVED_CONTROL_LOW, control & 0xfffc != 0);
+        violations.set(
+            UpidViolations::RESERVED_CONTROL_HIGH,
+            control & 0xff00_0000 != 0,
+        );
+        violations
+    }
+
     pub fn set_notification_enabled(&mut self, enabled: bool) {
         self.control
             .modify(NotificationControl::SUPPRESSED.val(!enabled as _));
@@ -53,6 +192,224 @@ impl Upid {
         self.control
             .modify(NotificationControl::OUTSTANDING.val(outstanding as _));
     }
+
+    pub fn destination(&self, mode: ApicMode) -> ApicId {
+        ApicId::from_destination(
+            self.control.read(NotificationControl::DESTINATION) as u32,
+            mode,
+        )
+    }
+
+    pub fn set_destination(&mut self, destination: ApicId) {
+        self.control
+            .modify(NotificationControl::DESTINATION.val(destination.to_destination() as _));
+    }
+}
+
+/// An ordinary interrupt that must be sent to notify the receiver of a
+/// posted user interrupt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Notification {
+    /// Target physical APIC ID, the raw DESTINATION field of the UPID.
+    pub destination: u32,
+    /// Notification vector, the VECTOR field of the UPID.
+    pub vector: u8,
+}
+
+impl Notification {
+    fn from_control(control: NotificationControlLocal) -> Self {
+        Self {
+            destination: control.read(NotificationControl::DESTINATION) as u32,
+            vector: control.read(NotificationControl::VECTOR) as u8,
+        }
+    }
+
+    pub fn apic_id(&self, mode: ApicMode) -> ApicId {
+        ApicId::from_destination(self.destination, mode)
+    }
+}
+
+/// UPID accessed with locked read-modify-write operations.
+///
+/// A live UPID is concurrently written by SENDUIPI and by user-interrupt
+/// notification processing on other logical processors, so plain
+/// read-modify-write through [`Upid`] can lose posted interrupts or
+/// notification bits. This type has the same layout as [`Upid`].
+#[repr(C, align(64))]
+pub struct AtomicUpid {
+    control: AtomicU64,
+    posted_uirq: AtomicU64,
+}
+
+impl AtomicUpid {
+    pub fn new(outstanding: bool, suppressed: bool, notif_vector: u8, destination: ApicId) -> Self {
+        Self::from_upid(Upid::new(
+            outstanding,
+            suppressed,
+            notif_vector,
+            destination,
+        ))
+    }
+
+    pub fn from_upid(upid: Upid) -> Self {
+        Self {
+            control: AtomicU64::new(upid.control.get()),
+            posted_uirq: AtomicU64::new(upid.posted_uirq.get()),
+        }
+    }
+
+    /// View a UPID in memory as an [`AtomicUpid`].
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must point to a valid UPID that lives for `'a`, and that is only
+    /// accessed atomically (including by hardware) during `'a`.
+    pub unsafe fn from_ptr<'a>(ptr: *mut Upid) -> &'a Self {
+        unsafe { &*(ptr as *const Self) }
+    }
+
+    /// The address of this UPID, to be written to a UITT entry or IA32_UINTR_PD.
+    pub fn as_ptr(&self) -> *mut Upid {
+        self as *const Self as *mut Upid
+    }
+
+    /// Take a snapshot of the UPID.
+    pub fn load(&self) -> Upid {
+        Upid {
+            control: NotificationControlLocal::new(self.control.load(Ordering::SeqCst)),
+            posted_uirq: LocalRegisterCopy::new(self.posted_uirq.load(Ordering::SeqCst)),
+        }
+    }
+
+    /// Take a snapshot of the notification control word.
+    pub fn control(&self) -> NotificationControlLocal {
+        NotificationControlLocal::new(self.control.load(Ordering::SeqCst))
+    }
+
+    /// Set PIR bit `vector`. Returns whether it was already set.
+    ///
+    /// `vector` must be below 64, the number of user-interrupt vectors.
+    pub fn post_vector(&self, vector: u8) -> bool {
+        debug_assert!(vector < 64, "user-interrupt vector {vector} out of range");
+        let bit = 1 << (vector & 0x3f);
+        self.posted_uirq.fetch_or(bit, Ordering::SeqCst) & bit != 0
+    }
+
+    /// Atomically read and clear PIR.
+    pub fn fetch_and_clear_pir(&self) -> u64 {
+        self.posted_uirq.swap(0, Ordering::SeqCst)
+    }
+
+    /// Set ON. Returns whether it was already set.
+    pub fn test_and_set_outstanding(&self) -> bool {
+        let bit = NotificationControl::OUTSTANDING::SET.value;
+        self.control.fetch_or(bit, Ordering::SeqCst) & bit != 0
+    }
+
+    /// Clear ON. Returns whether it was set.
+    pub fn test_and_clear_outstanding(&self) -> bool {
+        let bit = NotificationControl::OUTSTANDING::SET.value;
+        self.control.fetch_and(!bit, Ordering::SeqCst) & bit != 0
+    }
+
+    pub fn destination(&self, mode: ApicMode) -> ApicId {
+        ApicId::from_destination(
+            self.control().read(NotificationControl::DESTINATION) as u32,
+            mode,
+        )
+    }
+
+    /// Atomically replace the DESTINATION field, preserving ON, SN and VECTOR.
+    pub fn set_destination(&self, destination: ApicId) {
+        let field = NotificationControl::DESTINATION.val(destination.to_destination() as _);
+        let _ = self
+            .control
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |control| {
+                Some((control & !NotificationControl::DESTINATION::SET.mask()) | field.value)
+            });
+    }
+
+    /// Retarget notifications to `destination` after the receiving thread
+    /// migrated to another logical processor.
+    ///
+    /// A notification for interrupts already posted in PIR may have been sent
+    /// to the old processor. Unless SN is set, such interrupts are notified
+    /// again: ON is set and the notification is returned, to be sent by the
+    /// caller as an ordinary IPI.
+    pub fn retarget(&self, destination: ApicId) -> Option<Notification> {
+        self.set_destination(destination);
+        let control = self.control();
+        if control.is_set(NotificationControl::SUPPRESSED)
+            || self.posted_uirq.load(Ordering::SeqCst) == 0
+        {
+            return None;
+        }
+        self.test_and_set_outstanding();
+        Some(Notification::from_control(control))
+    }
+
+    /// Set SN, so that posting agents do not send notifications.
+    pub fn set_suppressed(&self) {
+        let bit = NotificationControl::SUPPRESSED::SET.value;
+        self.control.fetch_or(bit, Ordering::SeqCst);
+    }
+
+    /// Clear SN, so that posting agents send notifications again.
+    pub fn clear_suppressed(&self) {
+        let bit = NotificationControl::SUPPRESSED::SET.value;
+        self.control.fetch_and(!bit, Ordering::SeqCst);
+    }
+}
+
+impl AtomicUpid {
+    /// Post a user interrupt with `uintr_vector` the way SENDUIPI does.
+    ///
+    /// The PIR bit is set first. Then, if neither SN nor ON is set, ON is set
+    /// and the notification to be sent is returned. It is the caller's
+    /// responsibility to send it as an ordinary IPI.
+    ///
+    /// `uintr_vector` must be below 64, see [`AtomicUpid::post_vector`].
+    pub fn post(&self, uintr_vector: u8) -> Option<Notification> {
+        self.post_vector(uintr_vector);
+        let mut control = self.control.load(Ordering::SeqCst);
+        loop {
+            let current = NotificationControlLocal::new(control);
+            if current.is_set(NotificationControl::SUPPRESSED)
+                || current.is_set(NotificationControl::OUTSTANDING)
+            {
+                return None;
+            }
+            match self.control.compare_exchange_weak(
+                control,
+                control | NotificationControl::OUTSTANDING::SET.value,
+                Ordering::SeqCst,
+                Ordering::SeqCst,
+            ) {
+                Ok(_) => return Some(Notification::from_control(current)),
+                Err(actual) => control = actual,
+            }
+        }
+    }
+
+    /// Post a user interrupt with `uintr_vector` and call `send_ipi` if a
+    /// notification must be sent, see [`AtomicUpid::post`].
+    ///
+    /// Returns whether a notification was sent.
+    pub fn post_and_notify<F: FnOnce(Notification)>(&self, uintr_vector: u8, send_ipi: F) -> bool {
+        match self.post(uintr_vector) {
+            Some(notification) => {
+                send_ipi(notification);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl Debug for AtomicUpid {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        self.load().fmt(f)
+    }
 }
 
 impl Debug for Upid {
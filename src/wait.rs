@@ -0,0 +1,160 @@
+//! Sleeping until a user interrupt arrives.
+//!
+//! The handler entry records every delivery in a flag, which the wait
+//! functions check and consume. Checking the flag and going to sleep must not
+//! race with a delivery in between, which would be missed until the next
+//! user interrupt:
+//!
+//! - [`wait_umwait`] arms UMONITOR on the flag before checking it, so that a
+//!   delivery after the check ends the following UMWAIT.
+//! - [`wait_kernel`] checks the flag and blocks in the kernel with UIF
+//!   cleared, so that user interrupts stay pending and end the system call.
+
+use core::{
+    arch::asm,
+    hint::spin_loop,
+    sync::atomic::{AtomicBool, AtomicU8, Ordering},
+};
+
+use crate::{
+    detect::UintrCapabilities,
+    instructions::{clui, stui},
+    kernel::UintrKernel,
+};
+
+/// Default time limit of a single UMWAIT, in TSC cycles.
+pub const UMWAIT_TIMEOUT: u64 = 100_000;
+
+// Set by the handler entry. With the `per_thread` feature, every receiving
+// thread has its own flag.
+#[cfg_attr(feature = "per_thread", thread_local)]
+static DELIVERED: AtomicBool = AtomicBool::new(false);
+
+const WAITPKG_UNKNOWN: u8 = 0;
+const WAITPKG_ABSENT: u8 = 1;
+const WAITPKG_PRESENT: u8 = 2;
+
+static WAITPKG: AtomicU8 = AtomicU8::new(WAITPKG_UNKNOWN);
+
+pub(crate) fn mark_delivered() {
+    DELIVERED.store(true, Ordering::SeqCst);
+}
+
+/// Whether a user interrupt has been delivered since the last call, or
+/// since a wait function returned.
+pub fn take_delivered() -> bool {
+    // Only write the flag if it is set, which would end UMWAIT otherwise.
+    DELIVERED.load(Ordering::SeqCst) && DELIVERED.swap(false, Ordering::SeqCst)
+}
+
+/// Whether UMONITOR and UMWAIT are available.
+pub fn has_waitpkg() -> bool {
+    match WAITPKG.load(Ordering::Relaxed) {
+        WAITPKG_UNKNOWN => {
+            let waitpkg = UintrCapabilities::detect().waitpkg;
+            let state = if waitpkg {
+                WAITPKG_PRESENT
+            } else {
+                WAITPKG_ABSENT
+            };
+            WAITPKG.store(state, Ordering::Relaxed);
+            waitpkg
+        }
+        state => state == WAITPKG_PRESENT,
+    }
+}
+
+/// Wait until a user interrupt is delivered, with UMWAIT if the processor
+/// supports it, or with [`wait_kernel`] otherwise.
+///
+/// UIF is set when this function returns.
+pub fn wait<K: UintrKernel>(kernel: &K) -> Result<(), K::Error> {
+    if has_waitpkg() {
+        unsafe { wait_umwait(UMWAIT_TIMEOUT) };
+        Ok(())
+    } else {
+        wait_kernel(kernel)
+    }
+}
+
+/// Wait until a user interrupt is delivered, by spinning on the flag.
+///
+/// UIF is set when this function returns.
+pub fn wait_spin() {
+    stui();
+    while !take_delivered() {
+        spin_loop();
+    }
+}
+
+/// Wait until a user interrupt is delivered, with UMWAIT in C0.2 for at most
+/// `timeout` TSC cycles at a time.
+///
+/// UIF is set when this function returns.
+///
+/// # Safety
+///
+/// The processor must support WAITPKG, see [`has_waitpkg`].
+pub unsafe fn wait_umwait(timeout: u64) {
+    loop {
+        unsafe { umonitor(DELIVERED.as_ptr() as *const u8) };
+        clui();
+        if take_delivered() {
+            stui();
+            return;
+        }
+        // A delivery from here on writes the monitored flag.
+        stui();
+        let deadline = unsafe { core::arch::x86_64::_rdtsc() }.wrapping_add(timeout);
+        unsafe { umwait(deadline) };
+    }
+}
+
+/// Wait until a user interrupt is delivered, with [`UintrKernel::wait`].
+///
+/// The kernel is entered with UIF cleared, so user interrupts that arrive
+/// after the flag has been checked stay pending and end the system call,
+/// instead of being delivered before it. UIF is set when this function
+/// returns.
+pub fn wait_kernel<K: UintrKernel>(kernel: &K) -> Result<(), K::Error> {
+    loop {
+        clui();
+        if take_delivered() {
+            stui();
+            return Ok(());
+        }
+        let result = kernel.wait();
+        // Deliver the pending user interrupts, which set the flag.
+        stui();
+        result?;
+    }
+}
+
+/// UMONITOR: arm address monitoring of the cache line containing `addr`.
+#[inline]
+unsafe fn umonitor(addr: *const u8) {
+    unsafe {
+        asm!(
+            // umonitor rax
+            ".byte 0xf3, 0x0f, 0xae, 0xf0",
+            in("rax") addr,
+            options(nostack, preserves_flags)
+        )
+    }
+}
+
+/// UMWAIT: wait in C0.2 until the monitor triggers, an interrupt arrives or
+/// the TSC reaches `deadline`.
+#[inline]
+unsafe fn umwait(deadline: u64) {
+    unsafe {
+        asm!(
+            // umwait ecx
+            ".byte 0xf2, 0x0f, 0xae, 0xf1",
+            in("ecx") 0,
+            in("eax") deadline as u32,
+            in("edx") (deadline >> 32) as u32,
+            options(nostack)
+        )
+    }
+}